@@ -0,0 +1,7 @@
+#!/bin/sh
+# Replace the bundled database with the latest programs.json of the community
+# chip-8 database, the emulator is then rebuilt to embed it
+set -e
+cd "$(dirname "$0")"
+curl -fsSL -o programs.json \
+    https://raw.githubusercontent.com/chip-8/chip-8-database/master/database/programs.json
@@ -0,0 +1,183 @@
+use crate::chip::MAX_CALL_DEPTH;
+use crate::error::Error;
+use crate::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+pub struct CallGraph {
+    dot: Option<PathBuf>,
+    folded: Option<PathBuf>,
+    // Entry addresses of the subroutines being executed, starting from the program entry
+    stack: Vec<u16>,
+    // Observed (caller, callee) `2NNN` edges
+    calls: BTreeMap<(u16, u16), u64>,
+    // `00EE` returns per subroutine
+    returns: BTreeMap<u16, u64>,
+    max_depth: usize,
+    // Instructions executed per call stack
+    samples: HashMap<Vec<u16>, u64>,
+}
+
+impl CallGraph {
+    /// Export as Graphviz DOT and/or folded stacks, rooted at the program `entry`
+    pub fn new(dot: Option<PathBuf>, folded: Option<PathBuf>, entry: u16) -> Self {
+        Self {
+            dot,
+            folded,
+            stack: vec![entry],
+            calls: BTreeMap::new(),
+            returns: BTreeMap::new(),
+            max_depth: 0,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, opcode: u16) {
+        match self.samples.get_mut(&self.stack[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.samples.insert(self.stack.clone(), 1);
+            }
+        }
+
+        let caller = *self.stack.last().unwrap();
+        if opcode & 0xF000 == 0x2000 {
+            let callee = opcode & 0x0FFF;
+            *self.calls.entry((caller, callee)).or_default() += 1;
+            // The oldest call is lost like when the machine's stack wraps around,
+            // the program entry stays the root
+            if self.stack.len() > MAX_CALL_DEPTH {
+                self.stack.remove(1);
+            }
+            self.stack.push(callee);
+            self.max_depth = self.max_depth.max(self.stack.len() - 1);
+        } else if opcode == 0x00EE {
+            *self.returns.entry(caller).or_default() += 1;
+            // Returning from the top level leaves the root in place
+            if self.stack.len() > 1 {
+                self.stack.pop();
+            }
+        }
+    }
+
+    fn node(&self, addr: u16) -> String {
+        if addr == self.stack[0] {
+            format!("main {:#06X}", addr)
+        } else {
+            format!("{:#06X}", addr)
+        }
+    }
+
+    fn write_dot(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(out, "digraph calls {{")?;
+        writeln!(out, "    label=\"max stack depth {}\";", self.max_depth)?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        let mut nodes = vec![self.stack[0]];
+        for &(caller, callee) in self.calls.keys() {
+            nodes.push(caller);
+            nodes.push(callee);
+        }
+        nodes.sort_unstable();
+        nodes.dedup();
+        for addr in nodes {
+            let returns = self.returns.get(&addr).copied().unwrap_or(0);
+            writeln!(
+                out,
+                "    \"{:#06X}\" [label=\"{}\\nreturns: {}\"];",
+                addr,
+                self.node(addr),
+                returns
+            )?;
+        }
+        for (&(caller, callee), count) in &self.calls {
+            writeln!(
+                out,
+                "    \"{:#06X}\" -> \"{:#06X}\" [label=\"{}\"];",
+                caller, callee, count
+            )?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    // `main 0x0200;0x02A4;0x0300 count`, the input of flame graph tools
+    fn write_folded(&self, out: &mut impl Write) -> Result<()> {
+        let mut stacks = self
+            .samples
+            .iter()
+            .map(|(stack, count)| {
+                let frames = stack
+                    .iter()
+                    .map(|&addr| self.node(addr))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                (frames, count)
+            })
+            .collect::<Vec<_>>();
+        stacks.sort();
+        for (frames, count) in stacks {
+            writeln!(out, "{} {}", frames, count)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self) -> Result<String> {
+        let mut summary = format!(
+            "Call graph: {} call edges, max stack depth {}\n",
+            self.calls.len(),
+            self.max_depth
+        );
+        if let Some(path) = &self.dot {
+            let mut out = BufWriter::new(File::create(path).map_err(Error::file(path))?);
+            self.write_dot(&mut out)?;
+            out.flush()?;
+            summary.push_str(&format!("Call graph written to {}\n", path.display()));
+        }
+        if let Some(path) = &self.folded {
+            let mut out = BufWriter::new(File::create(path).map_err(Error::file(path))?);
+            self.write_folded(&mut out)?;
+            out.flush()?;
+            summary.push_str(&format!("Folded stacks written to {}\n", path.display()));
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_exports_calls() -> Result<()> {
+        let mut graph = CallGraph::new(None, None, 0x200);
+        // CALL 0x300, CALL 0x400, RET, RET, CALL 0x300, RET
+        for &opcode in &[0x2300, 0x2400, 0x00EE, 0x00EE, 0x2300, 0x00EE] {
+            graph.record(opcode);
+        }
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot)?;
+        insta::assert_snapshot!(String::from_utf8(dot).unwrap(), @r###"
+        digraph calls {
+            label="max stack depth 2";
+            node [shape=box, fontname=monospace];
+            "0x0200" [label="main 0x0200\nreturns: 0"];
+            "0x0300" [label="0x0300\nreturns: 2"];
+            "0x0400" [label="0x0400\nreturns: 1"];
+            "0x0200" -> "0x0300" [label="2"];
+            "0x0300" -> "0x0400" [label="1"];
+        }
+        "###);
+
+        let mut folded = Vec::new();
+        graph.write_folded(&mut folded)?;
+        insta::assert_snapshot!(String::from_utf8(folded).unwrap(), @r###"
+        main 0x0200 2
+        main 0x0200;0x0300 3
+        main 0x0200;0x0300;0x0400 1
+        "###);
+        Ok(())
+    }
+}
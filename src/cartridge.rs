@@ -0,0 +1,223 @@
+use crate::config::Settings;
+use crate::error::Error;
+use crate::octo;
+use crate::quirks::Quirks;
+use crate::theme::{format_color, parse_color};
+use crate::Result;
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::Path;
+
+/// A program shared as an Octo cartridge
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    // Instructions per frame at 60 frames per second
+    pub tickrate: Option<u32>,
+    pub quirks: Quirks,
+    // Pixel and background colors
+    pub colors: Option<(Color, Color)>,
+}
+
+// What Octo stores in a cartridge, the program is Octo source
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Options {
+    tickrate: Option<u32>,
+    fill_color: Option<String>,
+    background_color: Option<String>,
+    shift_quirks: bool,
+    load_store_quirks: bool,
+    clip_quirks: bool,
+    jump_quirks: bool,
+    v_blank_quirks: bool,
+    logic_quirks: bool,
+}
+
+pub fn is_cartridge<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"))
+}
+
+impl Cartridge {
+    pub fn settings(&self) -> Settings {
+        let (fill_color, background_color) = match self.colors {
+            Some((fill, background)) => (Some(format_color(fill)), Some(format_color(background))),
+            None => (None, None),
+        };
+        Settings {
+            fps: self.tickrate.map(|t| t * 60),
+            fill_color,
+            background_color,
+            quirks: self.quirks.to_map(),
+            ..Settings::default()
+        }
+    }
+
+    /// Decode a cartridge GIF and assemble the program in it
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options
+            .read_info(File::open(&path).map_err(Error::file(&path))?)
+            .map_err(|e| Error::InvalidCartridge(e.to_string()))?;
+        let mut indices = Vec::new();
+        while let Some(frame) = decoder
+            .read_next_frame()
+            .map_err(|e| Error::InvalidCartridge(e.to_string()))?
+        {
+            indices.extend_from_slice(&frame.buffer);
+        }
+        Self::decode(&payload(&indices)?)
+    }
+
+    fn decode(json: &[u8]) -> Result<Self> {
+        let payload: Payload =
+            serde_json::from_slice(json).map_err(|e| Error::InvalidCartridge(e.to_string()))?;
+        let rom = octo::assemble(&payload.program).map_err(Error::InvalidCartridge)?;
+        let options = payload.options;
+        let colors = match (&options.fill_color, &options.background_color) {
+            (Some(fill), Some(background)) => Some((color(fill)?, color(background)?)),
+            _ => None,
+        };
+        Ok(Self {
+            rom,
+            tickrate: options.tickrate,
+            quirks: Quirks {
+                shift: options.shift_quirks,
+                memory_increment_by_x: false,
+                memory_leave_i_unchanged: options.load_store_quirks,
+                wrap: !options.clip_quirks,
+                jump: options.jump_quirks,
+                vblank: options.v_blank_quirks,
+                logic: options.logic_quirks,
+                key_wait_on_press: false,
+            },
+            colors,
+        })
+    }
+}
+
+// Octo hides 2 bits in each pixel's palette index, most significant first,
+// making up a 4 byte big endian length followed by that many bytes of JSON
+fn payload(indices: &[u8]) -> Result<Vec<u8>> {
+    let bytes = indices
+        .chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0, |byte, p| byte << 2 | (p & 3)))
+        .collect::<Vec<u8>>();
+    if bytes.len() < 4 {
+        return Err(Error::InvalidCartridge("No payload".to_string()));
+    }
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    bytes
+        .get(4..4 + size)
+        .map(|json| json.to_vec())
+        .ok_or_else(|| Error::InvalidCartridge(format!("Truncated payload of {} bytes", size)))
+}
+
+fn color(hex: &str) -> Result<Color> {
+    parse_color(hex).ok_or_else(|| Error::InvalidCartridge(format!("Invalid color {}", hex)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Palette indices carry the label drawn on the cartridge in the upper bits
+    fn indices(json: &[u8]) -> Vec<u8> {
+        let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(json);
+        bytes
+            .iter()
+            .flat_map(|b| (0..4).rev().map(move |k| 0x40 | (b >> (k * 2)) & 3))
+            .collect()
+    }
+
+    // A GIF with the payload spread over frames of 64 pixels wide
+    fn cartridge(dir: &Path, json: &[u8]) -> std::path::PathBuf {
+        const WIDTH: usize = 64;
+        let mut indices = indices(json);
+        let rows = indices.len().div_ceil(WIDTH);
+        indices.resize(rows * WIDTH, 0x40);
+        let palette = (0..=255).flat_map(|c| vec![c, c, c]).collect::<Vec<u8>>();
+        let path = dir.join("cartridge.gif");
+        let mut encoder =
+            gif::Encoder::new(File::create(&path).unwrap(), WIDTH as u16, 4, &palette).unwrap();
+        for frame in indices.chunks(WIDTH * 4) {
+            let height = frame.len() / WIDTH;
+            let frame = gif::Frame::from_indexed_pixels(WIDTH as u16, height as u16, frame, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn it_decodes_payload() {
+        let json = br##"{"program":": main 0x60 0x2A","options":{"tickrate":20,"fillColor":"#FFCC00","backgroundColor":"#996600","clipQuirks":true}}"##;
+        let cartridge = Cartridge::decode(&payload(&indices(json)).unwrap()).unwrap();
+        assert_eq!(cartridge.rom, vec![0x60, 0x2A]);
+        assert_eq!(cartridge.tickrate, Some(20));
+        assert!(!cartridge.quirks.wrap);
+        assert_eq!(
+            cartridge.colors,
+            Some((
+                Color::Rgb {
+                    r: 0xFF,
+                    g: 0xCC,
+                    b: 0
+                },
+                Color::Rgb {
+                    r: 0x99,
+                    g: 0x66,
+                    b: 0
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn it_reads_cartridge_gifs() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = r#"
+            :macro set-both value { v0 := value v1 := value }
+            :calc CENTER { 64 - 4 * 2 }
+            : main
+              set-both CENTER
+              set-both 1
+              loop again
+        "#;
+        let json = serde_json::json!({
+            "program": program,
+            "options": { "tickrate": 500, "shiftQuirks": true, "vBlankQuirks": true }
+        });
+        let path = cartridge(dir.path(), json.to_string().as_bytes());
+
+        let cartridge = Cartridge::read(&path).unwrap();
+        assert_eq!(
+            cartridge.rom,
+            vec![0x12, 0x02, 0x60, 0x38, 0x61, 0x38, 0x60, 0x01, 0x61, 0x01, 0x12, 0x0A]
+        );
+        assert_eq!(cartridge.tickrate, Some(500));
+        assert!(cartridge.quirks.shift);
+        assert!(cartridge.quirks.vblank);
+        assert!(!cartridge.quirks.jump);
+        assert_eq!(cartridge.colors, None);
+
+        let json = serde_json::json!({ "program": ": main :stringmode x 0 { :byte 0 }" });
+        let path = self::cartridge(dir.path(), json.to_string().as_bytes());
+        match Cartridge::read(&path) {
+            Err(Error::InvalidCartridge(e)) => {
+                assert_eq!(e, "line 1: Unsupported Octo feature :stringmode")
+            }
+            _ => panic!("Cartridge with :stringmode read"),
+        }
+    }
+}
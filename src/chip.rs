@@ -1,12 +1,88 @@
-use crate::graphics::Graphics;
+use crate::callgraph::CallGraph;
+use crate::coverage::Coverage;
+use crate::crash::{self, Dump};
+use crate::debugger::{DebugCommand, Debugger};
+use crate::disasm;
+use crate::error::{Context, Error};
+use crate::graphics::{Graphics, Renderer, DISASSEMBLY_LINES};
 use crate::keyboard::Keyboard;
+use crate::keymap::Keymap;
+use crate::profile::Profiler;
+use crate::quirks::Quirks;
+use crate::rom;
+use crate::theme::Theme;
+use crate::trace::{Record, Tracer};
 use crate::Result;
+use crossterm::event::KeyCode;
+use crossterm::terminal;
 use rand::random;
-use std::io::{stdout, Read, Stdout};
-use std::time::Instant;
-use std::{fs::File, path::Path};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{stdout, Stdout, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct Chip {
+/// What to do on memory accesses beyond 4K and stack overflows/underflows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundsPolicy {
+    // Wrap addresses to 12 bits like real hardware, the stack pointer around 16 levels
+    Wrap,
+    // Stop with an error
+    Trap,
+}
+
+impl FromStr for BoundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(BoundsPolicy::Wrap),
+            "trap" => Ok(BoundsPolicy::Trap),
+            _ => Err(format!("Unknown bounds policy {}", s)),
+        }
+    }
+}
+
+/// What to do on instructions that aren't part of the instruction set
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownPolicy {
+    // Stop with an error
+    Halt,
+    // Count it and go on with the next instruction
+    Skip,
+    // Break into the debugger at the instruction, it's skipped when going on
+    Debug,
+    // Treat `0NNN` machine code calls as no-ops, stop on anything else
+    #[serde(rename = "0nnn-as-nop")]
+    SysAsNop,
+}
+
+impl FromStr for UnknownPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "halt" => Ok(UnknownPolicy::Halt),
+            "skip" => Ok(UnknownPolicy::Skip),
+            "debug" => Ok(UnknownPolicy::Debug),
+            "0nnn-as-nop" => Ok(UnknownPolicy::SysAsNop),
+            _ => Err(format!("Unknown policy for unknown instructions {}", s)),
+        }
+    }
+}
+
+/// Calls the stack holds before it overflows, as its first level is never used
+pub const MAX_CALL_DEPTH: usize = 15;
+
+// Frames per second of the display, the rate of the timers
+const FRAME_RATE: u32 = 60;
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+pub struct Chip<W: Write = Stdout> {
     memory: [u8; 4096],
     // Registers
     v: [u8; 16],
@@ -16,16 +92,64 @@ pub struct Chip {
     // The stack pointer, used to point to the topmost level of the stack
     sp: u8,
     stack: [u16; 16],
-    gfx: Graphics<Stdout>,
+    gfx: Graphics<W>,
     keyboard: Keyboard,
     delay_timer: u8,
     sound_timer: u8,
     debug: bool,
+    debugger: Debugger,
     fps: u32,
+    bounds: BoundsPolicy,
+    on_unknown: UnknownPolicy,
+    quirks: Quirks,
+    // Cycle an unknown instruction was first skipped at and times it was, by address
+    // and opcode
+    skipped: BTreeMap<(u16, u16), (u64, u64)>,
+    // Whether `FX0A` is waiting for a key
+    waiting_for_key: bool,
+    // Whether `DXYN` ended the frame early, with the display wait quirk
+    vblank_wait: bool,
+    // Executed instructions since start
+    cycle: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    callgraph: Option<CallGraph>,
+    // Memory read as data and written by the current instruction
+    reads: Vec<u16>,
+    writes: Vec<(u16, u8)>,
+    // Where the ROM is loaded and its size
+    load_address: u16,
+    rom_size: usize,
+    // Problems with the ROM to report on exit
+    warnings: Vec<String>,
+    // Address and opcode of the instruction being executed
+    current: (u16, u16),
+    // Last executed instructions for crash reports
+    history: VecDeque<(u64, u16, u16)>,
 }
 
 impl Chip {
-    pub fn new(fps: u32, debug: bool) -> Self {
+    pub fn new(fps: u32, debug: bool, breakpoints: &[u16]) -> Result<Self> {
+        let gfx = Graphics::new(stdout())?;
+        let keyboard = Keyboard::new()?;
+        let mut chip = Chip::with_io(gfx, keyboard, fps, debug, breakpoints)?;
+        if let Ok((columns, rows)) = terminal::size() {
+            chip.gfx.resize(columns, rows)?;
+        }
+        Ok(chip)
+    }
+}
+
+impl<W: Write> Chip<W> {
+    // The machine drawing to `gfx` and reading keys from `keyboard`
+    fn with_io(
+        mut gfx: Graphics<W>,
+        keyboard: Keyboard,
+        fps: u32,
+        debug: bool,
+        breakpoints: &[u16],
+    ) -> Result<Self> {
         let fontset = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -46,20 +170,15 @@ impl Chip {
         ];
         let mut memory = [0; 4096];
 
-        for i in 0..80 {
-            memory[i] = fontset[i];
-        }
-
-        let mut gfx = Graphics::new(stdout()).expect("Initialize graphics successfully");
-        let keyboard = Keyboard::new().expect("Initialize keyboard successfully");
+        memory[..80].copy_from_slice(&fontset);
 
         if debug {
-            gfx.draw_debugger()
-                .expect("Initialize debugger successfully");
+            gfx.draw_debugger()?;
         }
 
-        Chip {
+        Ok(Chip {
             debug,
+            debugger: Debugger::new(breakpoints),
             memory,
             v: [0; 16],
             vi: 0,
@@ -71,65 +190,471 @@ impl Chip {
             delay_timer: 0,
             sound_timer: 0,
             fps,
+            bounds: BoundsPolicy::Wrap,
+            on_unknown: UnknownPolicy::Halt,
+            quirks: Quirks::default(),
+            skipped: BTreeMap::new(),
+            waiting_for_key: false,
+            vblank_wait: false,
+            cycle: 0,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            callgraph: None,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            load_address: 0x200,
+            rom_size: 0,
+            warnings: Vec::new(),
+            current: (0, 0),
+            history: VecDeque::with_capacity(crash::HISTORY),
+        })
+    }
+
+    pub fn set_bounds_policy(&mut self, policy: BoundsPolicy) {
+        self.bounds = policy;
+    }
+
+    pub fn set_unknown_policy(&mut self, policy: UnknownPolicy) {
+        self.on_unknown = policy;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keyboard.set_keymap(keymap);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) -> Result<()> {
+        Ok(self.gfx.set_renderer(renderer)?)
+    }
+
+    pub fn set_persistence(&mut self, frames: u8) {
+        self.gfx.set_persistence(frames);
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) -> Result<()> {
+        Ok(self.gfx.set_theme(theme)?)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        Ok(self.gfx.show_title(title)?)
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn set_callgraph(&mut self, callgraph: CallGraph) {
+        self.callgraph = Some(callgraph);
+    }
+
+    /// Write out what's been collected while running,
+    /// returns a summary to print once the terminal is restored
+    pub fn finish(&mut self) -> Result<String> {
+        self.gfx.move_to_end()?;
+        let mut summary = String::new();
+        for warning in &self.warnings {
+            summary.push_str(&format!("warning: {}\n", warning));
+        }
+        if !self.skipped.is_empty() {
+            summary.push_str("Skipped unknown instructions, in the order they were first met\n");
+            let mut skipped = self.skipped.iter().collect::<Vec<_>>();
+            skipped.sort_by_key(|(_, &(first, _))| first);
+            for (&(addr, opcode), &(first, count)) in skipped {
+                summary.push_str(&format!(
+                    "  {:#06X}  {:04X}  first at cycle {}, {}x\n",
+                    addr, opcode, first, count
+                ));
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        if let Some(profiler) = &self.profiler {
+            profiler.write_csv()?;
+            summary.push_str(&profiler.report());
+            summary.push_str(&format!(
+                "\nProfile written to {}\n",
+                profiler.path().display()
+            ));
         }
+        if let Some(coverage) = &self.coverage {
+            let start = self.load_address as usize;
+            let rom = start..start + self.rom_size;
+            summary.push_str(&coverage.write(&self.memory, rom)?);
+            summary.push_str(&format!(
+                "\nCoverage written to {}\n",
+                coverage.path().display()
+            ));
+        }
+        if let Some(callgraph) = &self.callgraph {
+            summary.push_str(&callgraph.write()?);
+        }
+        Ok(summary)
+    }
+
+    /// Dump the machine state after `error` stopped it, returns the report path
+    pub fn write_crash_report(&self, error: &Error) -> Result<PathBuf> {
+        Dump {
+            error,
+            cycle: self.cycle,
+            // The failing instruction rather than the next one
+            pc: self.current.0,
+            v: self.v,
+            i: self.vi,
+            sp: self.sp,
+            stack: self.stack,
+            dt: self.delay_timer,
+            st: self.sound_timer,
+            history: &self.history,
+            memory: &self.memory,
+            pixels: &self.gfx.pixels,
+        }
+        .write()
     }
 
-    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let mut f = File::open(path)?;
-        f.read(&mut self.memory[512..])?;
+    /// Report a problem with the program on exit
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Load a ROM read with `rom::read` and start executing it at `load_address`
+    pub fn load_bytes(&mut self, rom: &[u8], load_address: u16) -> Result<()> {
+        let (program, warning) = rom::fit(rom, load_address)?;
+        let start = load_address as usize;
+        self.memory[start..start + program.len()].copy_from_slice(program);
+        self.load_address = load_address;
+        self.rom_size = program.len();
+        self.pc = load_address;
+        self.warnings.extend(warning);
         Ok(())
     }
 
-    fn fetch_opcode(&mut self) -> u16 {
-        let hi_bits = self.memory[self.pc as usize];
-        let lo_bits = self.memory[self.pc as usize + 1];
-        self.pc += 2;
+    // Opcode at the program counter, wrapping around memory, for display
+    fn peek_opcode(&self) -> u16 {
+        let hi_bits = self.memory[self.pc as usize & 0xFFF];
+        let lo_bits = self.memory[(self.pc as usize + 1) & 0xFFF];
         (hi_bits as u16) << 8 | lo_bits as u16
     }
 
+    fn fetch_opcode(&mut self) -> Result<u16> {
+        let pc = self.memory_addr(self.pc as usize)?;
+        let hi_bits = self.memory[pc];
+        let lo_bits = self.memory[self.memory_addr(pc + 1)?];
+        self.pc = pc as u16 + 2;
+        Ok((hi_bits as u16) << 8 | lo_bits as u16)
+    }
+
     pub fn run(&mut self) -> Result<()> {
-        'frame: loop {
+        // Instructions left over when `fps` isn't a multiple of the frame rate
+        let mut budget = 0;
+        loop {
             let start = Instant::now();
-            let mut op_count = 0;
-            loop {
-                let time_frame = Instant::now().duration_since(start).as_millis();
+            if !self.frame(&mut budget)? {
+                return Ok(());
+            }
+            if let Some(rest) = FRAME.checked_sub(start.elapsed()) {
+                thread::sleep(rest);
+            }
+        }
+    }
 
-                if op_count == self.fps && time_frame >= 1000 {
-                    continue 'frame;
-                }
+    // Execute a frame's share of `fps` and end the frame, false when quitting. Below
+    // 60 fps some frames execute no instruction, the timers still count down every
+    // frame
+    fn frame(&mut self, budget: &mut u32) -> Result<bool> {
+        *budget += self.fps;
+        for _ in 0..*budget / FRAME_RATE {
+            if self.debug
+                && !self.keyboard.quit_requested()
+                && self.debugger.should_break(self.pc, self.sp)
+            {
+                // Show the upcoming instruction, wait for a command to go on
+                self.break_into_debugger()?;
+            }
+            if self.keyboard.quit_requested() {
+                return Ok(false);
+            }
+            self.step()?;
+            if self.vblank_wait {
+                break;
+            }
+        }
+        *budget %= FRAME_RATE;
+
+        self.vblank()?;
+        Ok(!self.keyboard.quit_requested())
+    }
+
+    // The end of a frame: the display shows what was drawn, timers count down
+    fn vblank(&mut self) -> Result<()> {
+        self.vblank_wait = false;
+        if let Some((columns, rows)) = self.keyboard.take_resize() {
+            self.gfx.resize(columns, rows)?;
+        }
+        self.gfx.draw()?;
+        self.gfx.fade();
+
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            println!("beep!");
+            self.sound_timer -= 1;
+        }
+
+        self.keyboard.poll();
+        Ok(())
+    }
+
+    // Execute one instruction and record it
+    fn step(&mut self) -> Result<()> {
+        let pc = self.pc;
+        let opcode = self.peek_opcode();
+        let v_before = self.v;
+        self.reads.clear();
+        self.writes.clear();
+        if self.history.len() == crash::HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.cycle + 1, pc, opcode));
+
+        // Fetch opcode and execute
+        self.exec_cycle()?;
+        self.cycle += 1;
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&Record {
+                cycle: self.cycle,
+                pc,
+                opcode,
+                mnemonic: disasm::mnemonic(opcode).unwrap_or_else(|| "???".to_string()),
+                v: self.v,
+                changed: Record::changed(&v_before, &self.v),
+                i: self.vi,
+                dt: self.delay_timer,
+                st: self.sound_timer,
+                writes: self.writes.clone(),
+            })?;
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, opcode);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, &self.reads, &self.writes);
+        }
+        if let Some(callgraph) = &mut self.callgraph {
+            callgraph.record(opcode);
+        }
+        Ok(())
+    }
+
+    // Machine state for errors raised by the current instruction
+    fn context(&self) -> Context {
+        Context {
+            pc: self.current.0,
+            opcode: self.current.1,
+            v: self.v,
+            i: self.vi,
+            sp: self.sp,
+        }
+    }
+
+    // Check an address against the memory size, applying the bounds policy when beyond
+    fn memory_addr(&self, addr: usize) -> Result<usize> {
+        match self.bounds {
+            _ if addr < self.memory.len() => Ok(addr),
+            BoundsPolicy::Wrap => Ok(addr & 0xFFF),
+            BoundsPolicy::Trap => Err(Error::MemoryOutOfRange {
+                addr,
+                context: self.context(),
+            }),
+        }
+    }
 
-                if op_count < self.fps {
-                    if self.debug {
-                        self.gfx.log_op("NEXT OP: Press n to fetch")?;
-                        // Log previous result, press next to fetch next opcode
-                        self.gfx.log_values(self.v, self.pc, self.vi)?;
-                        Keyboard::block_until_press_next();
+    fn read_memory(&mut self, addr: usize) -> Result<u8> {
+        let addr = self.memory_addr(addr)?;
+        self.reads.push(addr as u16);
+        Ok(self.memory[addr])
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u8) -> Result<()> {
+        let addr = self.memory_addr(addr)?;
+        self.memory[addr] = value;
+        self.writes.push((addr as u16, value));
+        Ok(())
+    }
+
+    fn break_into_debugger(&mut self) -> Result<()> {
+        self.debugger.step(self.pc);
+        let half_window = (DISASSEMBLY_LINES / 2) as u16 * 2;
+        loop {
+            self.show_debugger()?;
+            match self.keyboard.block_until_debug_command() {
+                DebugCommand::Next | DebugCommand::Quit => return Ok(()),
+                DebugCommand::StepOver => {
+                    let opcode = self.peek_opcode();
+                    self.debugger.step_over(opcode, self.sp);
+                    return Ok(());
+                }
+                DebugCommand::StepOut => {
+                    self.debugger.step_out(self.sp);
+                    return Ok(());
+                }
+                DebugCommand::Continue => {
+                    self.debugger.resume();
+                    return Ok(());
+                }
+                DebugCommand::RunToCursor => {
+                    self.debugger.run_to(self.debugger.cursor());
+                    return Ok(());
+                }
+                DebugCommand::RunToAddress => {
+                    if let Some(addr) = self.prompt_address()? {
+                        self.debugger.run_to(addr);
+                        return Ok(());
+                    }
+                }
+                DebugCommand::CursorUp => {
+                    if self.debugger.cursor() > self.pc.saturating_sub(half_window) {
+                        self.debugger.move_cursor(-1);
                     }
-                    // Fetch opcode and execute
-                    self.exec_cycle()?;
-                    if self.debug {
-                        // Log next opcode, press next to log result
-                        Keyboard::block_until_press_next();
+                }
+                DebugCommand::CursorDown => {
+                    if self.debugger.cursor() < self.pc + half_window {
+                        self.debugger.move_cursor(1);
                     }
-                    op_count += 1;
                 }
+                DebugCommand::ToggleBreakpoint => {
+                    self.debugger.toggle_breakpoint(self.debugger.cursor())
+                }
+                DebugCommand::Resize => (),
             }
         }
     }
 
-    fn log_op(&mut self, opcode: u16, msg: &str) -> std::io::Result<()> {
-        if self.debug {
-            self.gfx.log_op(&format!(
-                "NEXT OP: {:#06X} {}, Press n to execute",
-                opcode, msg
-            ))?
+    // Read a hex address typed in the debugger, `None` if cancelled with Esc
+    fn prompt_address(&mut self) -> std::io::Result<Option<u16>> {
+        let mut input = String::new();
+        loop {
+            self.gfx.log_op(&format!("Run to address: 0x{}", input))?;
+            match self.keyboard.block_until_key() {
+                Some(KeyCode::Char(c)) if c.is_ascii_hexdigit() && input.len() < 3 => input.push(c),
+                Some(KeyCode::Backspace) => {
+                    input.pop();
+                }
+                Some(KeyCode::Enter) if !input.is_empty() => {
+                    return Ok(u16::from_str_radix(&input, 16).ok());
+                }
+                Some(KeyCode::Esc) => return Ok(None),
+                Some(_) => (),
+                // The prompt is shown again over the debugger
+                None => self.show_debugger()?,
+            }
+        }
+    }
+
+    fn show_debugger(&mut self) -> std::io::Result<()> {
+        // Laid out again for a resized terminal, which the debugger waits on
+        if let Some((columns, rows)) = self.keyboard.take_resize() {
+            self.gfx.resize(columns, rows)?;
+        }
+        // What was drawn so far in the frame
+        self.gfx.draw()?;
+        let before = (DISASSEMBLY_LINES / 2) as u16;
+        let lines = disasm::window(&self.memory, self.pc, before, before);
+        let current = lines.iter().position(|l| l.addr == self.pc).unwrap_or(0);
+        let lines = lines
+            .iter()
+            .map(|l| {
+                let breakpoint = if self.debugger.is_breakpoint(l.addr) {
+                    '●'
+                } else {
+                    ' '
+                };
+                let cursor = if l.addr == self.pc {
+                    '▶'
+                } else if l.addr == self.debugger.cursor() {
+                    '›'
+                } else {
+                    ' '
+                };
+                format!(
+                    "{}{} {:#06X}  {:04X}  {}",
+                    breakpoint, cursor, l.addr, l.opcode, l.text
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.gfx.log_op(
+            "n:step o:over u:out c:continue j/k:cursor r:run to cursor g:go to b:break q:quit",
+        )?;
+        self.gfx.log_values(self.v, self.pc, self.vi)?;
+        self.gfx.log_disassembly(&lines, current)
+    }
+
+    // Move I past the registers stored or loaded by `FX55`/`FX65`, as the quirks say
+    fn increment_i(&mut self, x: u8) {
+        if !self.quirks.memory_leave_i_unchanged {
+            let count = if self.quirks.memory_increment_by_x {
+                x
+            } else {
+                x + 1
+            };
+            self.vi = self.vi.wrapping_add(count as u16);
+        }
+    }
+
+    // Count a skip of the current instruction, the cycles match the ones of the trace
+    fn record_skip(&mut self) {
+        let cycle = self.cycle + 1;
+        self.skipped.entry(self.current).or_insert((cycle, 0)).1 += 1;
+    }
+
+    // Handle the current instruction not being decoded, according to the policy
+    fn unknown_opcode(&mut self) -> Result<()> {
+        let (addr, opcode) = self.current;
+        match self.on_unknown {
+            UnknownPolicy::SysAsNop if opcode & 0xF000 == 0 => Ok(()),
+            UnknownPolicy::Halt | UnknownPolicy::SysAsNop => {
+                Err(Error::UnknownOpcode(self.context()))
+            }
+            UnknownPolicy::Skip => {
+                self.record_skip();
+                Ok(())
+            }
+            UnknownPolicy::Debug => {
+                self.record_skip();
+                if !self.debug {
+                    self.debug = true;
+                    self.gfx.draw_debugger()?;
+                }
+                // Show it as upcoming, then go on after it
+                let next = self.pc;
+                self.pc = addr;
+                self.break_into_debugger()?;
+                self.pc = next;
+                Ok(())
+            }
         }
-        Ok(())
     }
 
     // Emulate one cycle
     fn exec_cycle(&mut self) -> Result<()> {
-        let opcode = self.fetch_opcode();
+        self.current = (self.pc, self.peek_opcode());
+        let opcode = self.fetch_opcode()?;
 
         // Decode and execute
         let x = ((opcode & 0x0F00) >> 8) as u8;
@@ -140,71 +665,73 @@ impl Chip {
 
         match opcode {
             0x00E0 => {
-                self.log_op(opcode, "CLS")?;
-                self.gfx.clear()?;
+                self.gfx.clear();
             }
             0x00EE => {
-                self.log_op(opcode, "RET")?;
+                if self.sp == 0 && self.bounds == BoundsPolicy::Trap {
+                    return Err(Error::StackUnderflow(self.context()));
+                }
                 self.pc = self.stack[self.sp as usize];
-                self.sp -= 1;
+                self.sp = self.sp.wrapping_sub(1) & 0xF;
             }
             _ => match opcode & 0xF000 {
                 0x1000 => {
-                    self.log_op(opcode, &format!("JP {:#06X}", nnn))?;
                     self.pc = nnn;
                 }
                 0x2000 => {
-                    self.log_op(opcode, &format!("CALL {:#06X}", nnn))?;
-                    self.sp += 1;
+                    if self.sp as usize + 1 >= self.stack.len() && self.bounds == BoundsPolicy::Trap
+                    {
+                        return Err(Error::StackOverflow(self.context()));
+                    }
+                    self.sp = (self.sp + 1) & 0xF;
                     self.stack[self.sp as usize] = self.pc;
                     self.pc = nnn;
                 }
                 0x3000 => {
-                    self.log_op(opcode, &format!("SE V{} {:#04X}", x, nn))?;
                     if self.v[x as usize] == nn {
                         self.pc += 2;
                     }
                 }
                 0x4000 => {
-                    self.log_op(opcode, &format!("SNE V{} {:#04X}", x, nn))?;
                     if self.v[x as usize] != nn {
                         self.pc += 2;
                     }
                 }
                 0x5000 => {
-                    self.log_op(opcode, &format!("SE V{} V{}", x, y))?;
                     if self.v[x as usize] == self.v[y as usize] {
                         self.pc += 2;
                     }
                 }
                 0x6000 => {
-                    self.log_op(opcode, &format!("LD V{} {:#04X}", x, nn))?;
                     self.v[x as usize] = nn;
                 }
                 0x7000 => {
-                    self.log_op(opcode, &format!("ADD V{} {:#04X}", x, nn))?;
                     let result = self.v[x as usize] as u16 + nn as u16;
                     self.v[x as usize] = result as u8;
                 }
                 0x8000 => match opcode & 0x000F {
                     0 => {
-                        self.log_op(opcode, &format!("LD V{} V{}", x, y))?;
                         self.v[x as usize] = self.v[y as usize];
                     }
                     1 => {
-                        self.log_op(opcode, &format!("OR V{} V{}", x, y))?;
                         self.v[x as usize] |= self.v[y as usize];
+                        if self.quirks.logic {
+                            self.v[0xF] = 0;
+                        }
                     }
                     2 => {
-                        self.log_op(opcode, &format!("AND V{} V{}", x, y))?;
                         self.v[x as usize] &= self.v[y as usize];
+                        if self.quirks.logic {
+                            self.v[0xF] = 0;
+                        }
                     }
                     3 => {
-                        self.log_op(opcode, &format!("XOR V{} V{}", x, y))?;
                         self.v[x as usize] ^= self.v[y as usize];
+                        if self.quirks.logic {
+                            self.v[0xF] = 0;
+                        }
                     }
                     4 => {
-                        self.log_op(opcode, &format!("ADD V{} V{}", x, y))?;
                         let result = self.v[x as usize] as u16 + self.v[y as usize] as u16;
                         // VF
                         self.v[0xF] = if result > 255 { 1 } else { 0 };
@@ -212,7 +739,6 @@ impl Chip {
                         self.v[x as usize] = result as u8;
                     }
                     5 => {
-                        self.log_op(opcode, &format!("SUB V{} V{}", x, y))?;
                         let vx = self.v[x as usize];
                         let vy = self.v[y as usize];
                         self.v[0xF] = if vx > vy { 1 } else { 0 };
@@ -223,66 +749,63 @@ impl Chip {
                         self.v[x as usize] = vx.wrapping_sub(vy);
                     }
                     7 => {
-                        self.log_op(opcode, &format!("SUBN V{} V{}", x, y))?;
                         let vx = self.v[x as usize];
                         let vy = self.v[y as usize];
                         self.v[0xF] = if vx < vy { 1 } else { 0 };
                         self.v[x as usize] = vy.wrapping_sub(vx);
                     }
                     6 => {
-                        self.log_op(opcode, &format!("SHR V{} {{, V{}}}", x, y))?;
-                        let vx = self.v[x as usize];
+                        let vx = self.v[if self.quirks.shift { x } else { y } as usize];
                         self.v[0xF] = if vx & 1 == 1 { 1 } else { 0 };
                         self.v[x as usize] = vx >> 1;
                     }
                     0xE => {
-                        self.log_op(opcode, &format!("SHL V{} {{, V{}}}", x, y))?;
-                        let vx = self.v[x as usize];
-                        self.v[0xF] = if vx & 0x10 == 1 { 1 } else { 0 };
+                        let vx = self.v[if self.quirks.shift { x } else { y } as usize];
+                        self.v[0xF] = vx >> 7;
                         self.v[x as usize] = vx << 1;
                     }
-                    _ => Err(format!("Unknown instruction {:#06X}", opcode))?,
+                    _ => self.unknown_opcode()?,
                 },
                 0x9000 if opcode & 1 == 0 => {
-                    self.log_op(opcode, &format!("SNE V{} V{}", x, y))?;
                     if self.v[x as usize] != self.v[y as usize] {
                         self.pc += 2;
                     }
                 }
                 0xA000 => {
-                    self.log_op(opcode, &format!("LD I {:#06X}", nnn))?;
                     self.vi = nnn;
                 }
                 0xB000 => {
-                    self.log_op(opcode, &format!("JP V0 {:#06X}", nnn))?;
-                    self.pc = nnn + self.v[0] as u16;
+                    let offset = if self.quirks.jump { x } else { 0 };
+                    self.pc = nnn + self.v[offset as usize] as u16;
                 }
                 0xC000 => {
-                    self.log_op(opcode, &format!("RND V{} {:#04X}", x, nn))?;
                     let rnd_byte = random::<u8>();
                     self.v[x as usize] = rnd_byte & nn;
                 }
                 0xD000 => {
-                    self.log_op(opcode, &format!("DRW V{} V{} {:#04X}", x, y, n))?;
                     // Read n bytes from memory(sprites = 8 * n pixel), starting at vi
                     let vi = self.vi as usize;
-                    let sprites = &self.memory[vi..(vi + n as usize)];
+                    let sprites = (0..n as usize)
+                        .map(|r| self.read_memory(vi + r))
+                        .collect::<Result<Vec<_>>>()?;
                     let x = self.v[x as usize] % 64;
                     let y = self.v[y as usize] % 32;
 
                     for (r, byte) in sprites.iter().enumerate() {
                         let y = y as usize + r;
                         // Out of vertical edge
-                        if y >= 32 {
+                        if y >= 32 && !self.quirks.wrap {
                             break;
                         }
+                        let y = y % 32;
 
                         for c in 0..8 {
                             let x = x as usize + c;
-                            if x >= 64 {
+                            if x >= 64 && !self.quirks.wrap {
                                 // Out of horizontal edge
                                 break;
                             }
+                            let x = x % 64;
                             let sprite_bit = (byte >> (7 - c)) & 1;
                             let screen_bit = self.gfx.pixels[y][x];
                             let pixel = sprite_bit ^ screen_bit;
@@ -297,91 +820,244 @@ impl Chip {
                             }
                         }
                     }
-                    self.gfx.draw()?;
+                    // The COSMAC VIP waits for the vertical blank to draw, leaving the rest
+                    // of the frame
+                    if self.quirks.vblank {
+                        self.vblank_wait = true;
+                    }
                 }
                 0xE000 if opcode & 0x00FF == 0x009E => {
-                    self.log_op(opcode, &format!("SKP V{}", x))?;
-                    let vx = self.v[x as usize] as usize;
+                    // Only the low nibble names a key, like the COSMAC VIP
+                    let vx = self.v[x as usize] as usize & 0xF;
                     if self.keyboard.get(vx) {
                         self.pc += 2;
                     }
                 }
                 0xE000 if opcode & 0x00FF == 0x00A1 => {
-                    self.log_op(opcode, &format!("SKNP V{}", x))?;
-                    let vx = self.v[x as usize] as usize;
+                    // Only the low nibble names a key, like the COSMAC VIP
+                    let vx = self.v[x as usize] as usize & 0xF;
                     if !self.keyboard.get(vx) {
                         self.pc += 2;
                     }
                 }
                 0xF000 => match opcode & 0x00FF {
                     0x07 => {
-                        self.log_op(opcode, &format!("LD V{} DT", x))?;
                         self.v[x as usize] = self.delay_timer;
                     }
                     0x0A => {
-                        self.log_op(opcode, &format!("LD V{} K", x))?;
-                        //  All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                        if let Some(k) = self.keyboard.find_pressed_key() {
-                            self.v[x as usize] = k;
-                        } else {
-                            self.pc -= 2;
+                        // Execution stops until a key goes down and back up, then its value
+                        // is stored in Vx. Keys held before don't count, timers keep running
+                        if !self.waiting_for_key {
+                            self.waiting_for_key = true;
+                            self.keyboard.forget_press();
+                        }
+                        match self.keyboard.fresh_press() {
+                            Some(k)
+                                if self.quirks.key_wait_on_press
+                                    || !self.keyboard.get(k as usize) =>
+                            {
+                                self.v[x as usize] = k;
+                                self.waiting_for_key = false;
+                            }
+                            _ => self.pc -= 2,
                         }
                     }
                     0x15 => {
-                        self.log_op(opcode, &format!("LD DT V{}", x))?;
                         self.delay_timer = self.v[x as usize];
                     }
                     0x18 => {
-                        self.log_op(opcode, &format!("LD ST V{}", x))?;
                         self.sound_timer = self.v[x as usize];
                     }
                     0x1E => {
-                        self.log_op(opcode, &format!("ADD I V{}", x))?;
-                        self.vi += self.v[x as usize] as u16;
+                        self.vi = self.vi.wrapping_add(self.v[x as usize] as u16);
                     }
                     0x29 => {
-                        self.log_op(opcode, &format!("LD F V{}", x))?;
                         self.vi = self.v[x as usize] as u16;
                     }
                     0x33 => {
-                        self.log_op(opcode, &format!("LD B V{}", x))?;
                         let vx = self.v[x as usize];
                         let vi = self.vi as usize;
-                        self.memory[vi] = vx / 100;
-                        self.memory[vi + 1] = (vx % 100) / 10;
-                        self.memory[vi + 2] = (vx % 100) % 10;
+                        self.write_memory(vi, vx / 100)?;
+                        self.write_memory(vi + 1, (vx % 100) / 10)?;
+                        self.write_memory(vi + 2, (vx % 100) % 10)?;
                     }
                     0x55 => {
-                        self.log_op(opcode, &format!("LD [I] V{}", x))?;
                         for i in 0..=x as usize {
                             let vi = self.vi as usize;
-                            self.memory[vi + i] = self.v[i];
+                            self.write_memory(vi + i, self.v[i])?;
                         }
+                        self.increment_i(x);
                     }
                     0x65 => {
-                        self.log_op(opcode, &format!("LD V{} [I]", x))?;
                         for i in 0..=x as usize {
                             let vi = self.vi as usize;
-                            self.v[i] = self.memory[vi + i];
+                            self.v[i] = self.read_memory(vi + i)?;
                         }
+                        self.increment_i(x);
                     }
-                    _ => Err(format!("Unknown instruction {:#06X}", opcode))?,
+                    _ => self.unknown_opcode()?,
                 },
-                _ => Err(format!("Unknown instruction {:#06X}", opcode))?,
+                _ => self.unknown_opcode()?,
             },
         }
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(program: &[u8], bounds: BoundsPolicy) -> Chip<Vec<u8>> {
+        chip_at(program, bounds, 600)
+    }
+
+    fn chip_at(program: &[u8], bounds: BoundsPolicy, fps: u32) -> Chip<Vec<u8>> {
+        let gfx = Graphics::new(Vec::new()).unwrap();
+        let mut chip = Chip::with_io(gfx, Keyboard::detached(), fps, false, &[]).unwrap();
+        chip.load_bytes(program, 0x200).unwrap();
+        chip.set_bounds_policy(bounds);
+        chip
+    }
+
+    // Run `steps` instructions, stopping at the first error
+    fn run(program: &[u8], bounds: BoundsPolicy, steps: usize) -> Result<Chip<Vec<u8>>> {
+        let mut chip = chip(program, bounds);
+        for _ in 0..steps {
+            chip.step()?;
         }
+        Ok(chip)
+    }
 
-        if self.sound_timer > 0 {
-            println!("beep!");
-            self.sound_timer -= 1;
+    #[test]
+    fn it_bounds_memory_and_stack_accesses() {
+        // LD I 0xFFF, then an access running past the end of memory
+        for access in [[0xD0, 0x1F], [0xF0, 0x33], [0xFF, 0x55], [0xFF, 0x65]] {
+            let program = [0xAF, 0xFF, access[0], access[1]];
+            assert!(run(&program, BoundsPolicy::Wrap, 2).is_ok());
+            let error = run(&program, BoundsPolicy::Trap, 2).err().unwrap();
+            assert!(matches!(
+                error,
+                Error::MemoryOutOfRange { addr: 0x1000, .. }
+            ));
         }
 
-        self.keyboard.poll();
+        // RET with nothing to return to
+        let ret = [0x00, 0xEE];
+        assert!(run(&ret, BoundsPolicy::Wrap, 1).is_ok());
+        let error = run(&ret, BoundsPolicy::Trap, 1).err().unwrap();
+        assert!(matches!(error, Error::StackUnderflow(_)));
+
+        // CALL 0x200 calling itself until the stack is full
+        let call = [0x22, 0x00];
+        assert!(run(&call, BoundsPolicy::Wrap, 32).is_ok());
+        let error = run(&call, BoundsPolicy::Trap, 32).err().unwrap();
+        assert!(matches!(
+            error,
+            Error::StackOverflow(Context { sp: 15, .. })
+        ));
+
+        // ADD I V0 past the largest address I holds
+        let mut chip = chip(&[0xF0, 0x1E], BoundsPolicy::Trap);
+        chip.vi = 0xFFFF;
+        chip.v[0] = 2;
+        chip.step().unwrap();
+        assert_eq!(chip.vi, 1);
+
+        // LD V0 0x20, SKP V0 and SKNP V0 use the low nibble as the key
+        for skip in [[0xE0, 0x9E], [0xE0, 0xA1]] {
+            let program = [0x60, 0x20, skip[0], skip[1]];
+            assert!(run(&program, BoundsPolicy::Trap, 2).is_ok());
+        }
+    }
+
+    #[test]
+    fn it_tells_when_unknown_instructions_were_skipped() {
+        // JP 0x204, unknown, unknown, JP 0x202
+        let program = [0x12, 0x04, 0x01, 0x23, 0x80, 0x08, 0x12, 0x02];
+        let mut chip = chip(&program, BoundsPolicy::Wrap);
+        chip.set_unknown_policy(UnknownPolicy::Skip);
+        for _ in 0..6 {
+            chip.step().unwrap();
+        }
+        let summary = chip.finish().unwrap();
+        assert!(summary.ends_with(
+            "  0x0204  8008  first at cycle 2, 2x\n  0x0202  0123  first at cycle 4, 1x\n"
+        ));
+    }
+
+    #[test]
+    fn it_counts_timers_down_once_per_frame() -> Result<()> {
+        // A loop on itself
+        for fps in [30, 60, 700, 2000] {
+            let mut chip = chip_at(&[0x12, 0x00], BoundsPolicy::Wrap, fps);
+            chip.delay_timer = 10;
+            let mut budget = 0;
+            for _ in 0..4 {
+                assert!(chip.frame(&mut budget)?);
+            }
+            assert_eq!(chip.delay_timer, 6, "at {} fps", fps);
+            // What's executed over a second averages to `fps`
+            for _ in 4..FRAME_RATE {
+                chip.frame(&mut budget)?;
+            }
+            assert_eq!(chip.cycle, fps as u64);
+            assert_eq!(chip.delay_timer, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_waits_for_vblank_after_drawing() -> Result<()> {
+        // LD V0 1, ADD V0 1, DRW V0 V0 1, then ADD V0 1 in a loop
+        let program = [0x60, 0x01, 0x70, 0x01, 0xD0, 0x01, 0x70, 0x01, 0x12, 0x06];
+        let waiting = || {
+            let mut chip = chip(&program, BoundsPolicy::Wrap);
+            chip.set_quirks(Quirks {
+                vblank: true,
+                ..Quirks::default()
+            });
+            chip
+        };
+        let mut chip = waiting();
+        chip.step()?;
+        chip.step()?;
+        assert!(!chip.vblank_wait);
+        chip.step()?;
+        assert!(chip.vblank_wait);
+        chip.step()?;
 
+        // The frame ends at the draw, the next one runs its 10 instructions
+        let mut chip = waiting();
+        let mut budget = 0;
+        chip.frame(&mut budget)?;
+        assert_eq!(chip.cycle, 3);
+        assert!(!chip.vblank_wait);
+        chip.frame(&mut budget)?;
+        assert_eq!(chip.cycle, 13);
+
+        // Without the quirk, drawing doesn't end the frame
+        let mut chip = chip_at(&program, BoundsPolicy::Wrap, 600);
+        chip.frame(&mut budget)?;
+        assert_eq!(chip.cycle, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn it_bounds_call_stacks_of_runaway_calls() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let folded = dir.path().join("calls.folded");
+        // CALL 0x200 calling itself, never returning
+        let mut chip = chip(&[0x22, 0x00], BoundsPolicy::Wrap);
+        chip.set_callgraph(CallGraph::new(None, Some(folded.clone()), 0x200));
+        for _ in 0..10_000 {
+            chip.step()?;
+        }
+        let summary = chip.finish()?;
+        assert!(summary.contains("max stack depth 15"));
+        // A stack per depth, from the root alone to 15 calls deep
+        assert_eq!(std::fs::read_to_string(folded)?.lines().count(), 16);
         Ok(())
     }
 }
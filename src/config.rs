@@ -0,0 +1,365 @@
+use crate::chip::{BoundsPolicy, UnknownPolicy};
+use crate::error::Error;
+use crate::graphics::Renderer;
+use crate::keymap::Keymap;
+use crate::quirks::Quirks;
+use crate::theme::{self, parse_color, Theme};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FPS: u32 = 700;
+pub const KEYMAP: &str = "hex";
+pub const THEME: &str = "default";
+
+/// Settings from the config file, the ROM database, a cartridge or the command
+/// line. Unset ones are left to the layer below
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+    // CHIP-8 keys of the ROM's controls, by name like in the ROM database
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub controls: BTreeMap<String, u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renderer: Option<Renderer>,
+    // A built-in theme, its colors and pixel replaced by the ones below
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    // `#RRGGBB`, an index in the 256 color palette or a name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border_color: Option<String>,
+    // Glyph of lit pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel: Option<char>,
+    // Frames cleared pixels fade out over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persistence: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<BoundsPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_unknown: Option<UnknownPolicy>,
+    // By name, replacing the ones of the layers below
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub quirks: BTreeMap<String, bool>,
+    // Keys no setting goes by, reported when loading the config
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Settings {
+    /// What's used when nothing is set
+    pub fn defaults() -> Self {
+        Self {
+            fps: Some(FPS),
+            keymap: Some(KEYMAP.to_string()),
+            controls: BTreeMap::new(),
+            renderer: Some(Renderer::Ascii),
+            theme: Some(THEME.to_string()),
+            fill_color: None,
+            background_color: None,
+            border_color: None,
+            pixel: None,
+            persistence: Some(0),
+            debug: Some(false),
+            bounds: Some(BoundsPolicy::Wrap),
+            on_unknown: Some(UnknownPolicy::Halt),
+            quirks: Quirks::default().to_map(),
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    /// These settings with the ones set in `other` taking precedence
+    pub fn merge(mut self, other: Settings) -> Self {
+        self.fps = other.fps.or(self.fps);
+        self.keymap = other.keymap.or(self.keymap);
+        self.controls.extend(other.controls);
+        self.renderer = other.renderer.or(self.renderer);
+        self.theme = other.theme.or(self.theme);
+        self.fill_color = other.fill_color.or(self.fill_color);
+        self.background_color = other.background_color.or(self.background_color);
+        self.border_color = other.border_color.or(self.border_color);
+        self.pixel = other.pixel.or(self.pixel);
+        self.persistence = other.persistence.or(self.persistence);
+        self.debug = other.debug.or(self.debug);
+        self.bounds = other.bounds.or(self.bounds);
+        self.on_unknown = other.on_unknown.or(self.on_unknown);
+        self.quirks.extend(other.quirks);
+        self
+    }
+
+    /// The layout with the controls of the ROM
+    pub fn keymap(&self) -> Result<Keymap> {
+        Keymap::load(self.keymap.as_deref().unwrap_or(KEYMAP))?.with_controls(&self.controls)
+    }
+
+    pub fn quirks(&self) -> Result<Quirks> {
+        Quirks::default()
+            .with(&self.quirks)
+            .map_err(Error::InvalidConfig)
+    }
+
+    pub fn theme(&self) -> Result<Theme> {
+        let name = self.theme.as_deref().unwrap_or(THEME);
+        let mut theme = Theme::named(name).ok_or_else(|| {
+            Error::InvalidConfig(format!(
+                "Unknown theme {}, expected one of {}",
+                name,
+                theme::NAMES.join(", ")
+            ))
+        })?;
+        let color = |color: &Option<String>, default| match color {
+            Some(color) => parse_color(color)
+                .map(Some)
+                .ok_or_else(|| Error::InvalidConfig(format!("Invalid color {}", color))),
+            None => Ok(default),
+        };
+        theme.fill = color(&self.fill_color, theme.fill)?;
+        theme.background = color(&self.background_color, theme.background)?;
+        theme.border = color(&self.border_color, theme.border)?;
+        theme.pixel = self.pixel.unwrap_or(theme.pixel);
+        Ok(theme)
+    }
+}
+
+/// The config file, `c8/config.toml` in the user's config directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub settings: Settings,
+    // Local ROM databases, searched before the ones given on the command line. `~` is
+    // the home directory, other relative paths are relative to the config file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub db: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_db: bool,
+    // Settings for a ROM by its file name or SHA-1
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rom: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("c8").join("config.toml"))
+    }
+
+    /// Read the file at `path`, or the default one if there is one
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(toml) => {
+                let mut config = Self::parse(&toml)
+                    .map_err(|e| Error::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+                config
+                    .check()
+                    .map_err(|e| Error::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+                config.resolve_paths(path.parent().unwrap_or_else(|| Path::new("")));
+                Ok(config)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(Error::InvalidConfig(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    fn parse(toml: &str) -> std::result::Result<Self, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    // Keys that aren't settings are likely typos, `db`, `no-db` and `rom` are only
+    // known at the top
+    fn check(&self) -> std::result::Result<(), String> {
+        let unknown = self
+            .settings
+            .unknown
+            .keys()
+            .cloned()
+            .chain(self.rom.iter().flat_map(|(rom, section)| {
+                section
+                    .unknown
+                    .keys()
+                    .map(move |key| format!("rom.\"{}\".{}", rom, key))
+            }));
+        match unknown.collect::<Vec<_>>() {
+            keys if keys.is_empty() => Ok(()),
+            keys => Err(format!("Unknown keys {}", keys.join(", "))),
+        }
+    }
+
+    // Paths of the databases and keymap files are relative to `dir`
+    fn resolve_paths(&mut self, dir: &Path) {
+        let home = dirs::home_dir();
+        let resolve = |path: &Path| match (path.strip_prefix("~"), &home) {
+            (Ok(rest), Some(home)) => home.join(rest),
+            _ => dir.join(path),
+        };
+        for path in &mut self.db {
+            *path = resolve(path);
+        }
+        let settings = std::iter::once(&mut self.settings).chain(self.rom.values_mut());
+        for keymap in settings.filter_map(|settings| settings.keymap.as_mut()) {
+            if !Keymap::PRESETS.contains(&keymap.as_str()) {
+                *keymap = resolve(Path::new(keymap)).to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    /// Settings of the sections for the ROM, by hash over by file name
+    pub fn for_rom(&self, name: Option<&str>, rom: &[u8]) -> Settings {
+        let hash = sha1_smol::Sha1::from(rom).digest().to_string();
+        [name, Some(&hash)]
+            .iter()
+            .flatten()
+            .filter_map(|key| self.rom.get(*key))
+            .fold(Settings::default(), |settings, section| {
+                settings.merge(section.clone())
+            })
+    }
+
+    pub fn dump(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    #[test]
+    fn it_reads_per_rom_sections() {
+        let config = Config::parse(
+            r##"
+            fps = 1000
+            keymap = "qwerty"
+            renderer = "braille"
+            background-color = "dark_grey"
+            pixel = "#"
+            db = ["local.json"]
+
+            [quirks]
+            wrap = true
+
+            [controls]
+            up = 5
+
+            [rom."IBM Logo.ch8"]
+            fps = 500
+            fill-color = "#FFCC00"
+
+            [rom.1ba58656810b67fd131eb9af3e3987863bf26c90]
+            on-unknown = "0nnn-as-nop"
+            quirks = { jump = true }
+            "##,
+        )
+        .unwrap();
+        let rom = include_bytes!("../rom/IBM Logo.ch8");
+
+        let settings = Settings::defaults()
+            .merge(config.settings.clone())
+            .merge(config.for_rom(Some("IBM Logo.ch8"), rom));
+        assert_eq!(settings.fps, Some(500));
+        assert_eq!(settings.keymap.as_deref(), Some("qwerty"));
+        let keymap = settings.keymap().unwrap();
+        assert_eq!(keymap.code(crossterm::event::KeyCode::Up), Some(0x5));
+        assert_eq!(settings.renderer, Some(Renderer::Braille));
+        assert_eq!(settings.on_unknown, Some(UnknownPolicy::SysAsNop));
+        let quirks = settings.quirks().unwrap();
+        assert!(quirks.wrap && quirks.jump && quirks.shift);
+        let theme = settings.theme().unwrap();
+        assert_eq!(
+            theme.fill,
+            Some(Color::Rgb {
+                r: 0xFF,
+                g: 0xCC,
+                b: 0
+            })
+        );
+        assert_eq!(theme.background, Some(Color::DarkGrey));
+        assert_eq!(theme.pixel, '#');
+        assert_eq!(
+            config.for_rom(Some("other.ch8"), &rom[1..]),
+            Settings::default()
+        );
+
+        let mut typo = Settings::default();
+        typo.quirks.insert("wrapp".to_string(), true);
+        assert!(typo.quirks().is_err());
+    }
+
+    #[test]
+    fn it_resolves_paths() {
+        let mut config = Config::parse(
+            r#"
+            db = ["local.json", "~/c8/db.json", "/db.json"]
+            keymap = "azerty.txt"
+
+            [rom.a]
+            keymap = "~/c8/dvorak.txt"
+
+            [rom.b]
+            keymap = "qwerty"
+            "#,
+        )
+        .unwrap();
+        config.resolve_paths(Path::new("/etc/c8"));
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            config.db,
+            vec![
+                PathBuf::from("/etc/c8/local.json"),
+                home.join("c8/db.json"),
+                PathBuf::from("/db.json")
+            ]
+        );
+        assert_eq!(
+            config.settings.keymap.as_deref(),
+            Some("/etc/c8/azerty.txt")
+        );
+        assert_eq!(
+            config.rom["a"].keymap.as_deref(),
+            home.join("c8/dvorak.txt").to_str()
+        );
+        assert_eq!(config.rom["b"].keymap.as_deref(), Some("qwerty"));
+    }
+
+    #[test]
+    fn it_rejects_unknown_keys() {
+        let config = Config::parse(
+            r##"
+            fps = 1000
+            fsp = 500
+            db = ["local.json"]
+
+            [rom."IBM Logo.ch8"]
+            pixle = "#"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(
+            config.check(),
+            Err(r#"Unknown keys fsp, rom."IBM Logo.ch8".pixle"#.to_string())
+        );
+        assert_eq!(config.settings.fps, Some(1000));
+        assert_eq!(config.db, vec![PathBuf::from("local.json")]);
+        assert_eq!(Config::parse("no-db = true").unwrap().check(), Ok(()));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::disasm;
+use crate::error::Error;
+use crate::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 1 << 1;
+const WRITTEN: u8 = 1 << 2;
+
+pub struct Coverage {
+    path: PathBuf,
+    // Access flags per memory byte
+    flags: Vec<u8>,
+}
+
+struct Row {
+    addr: usize,
+    bytes: Vec<u8>,
+    flags: u8,
+    // Mnemonic for code, sprite bitmap for data
+    text: String,
+}
+
+impl Coverage {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            flags: vec![0; 4096],
+        }
+    }
+
+    /// Mark the memory touched by an executed instruction
+    pub fn record(&mut self, pc: u16, reads: &[u16], writes: &[(u16, u8)]) {
+        let pc = pc as usize & 0xFFF;
+        self.flags[pc] |= EXECUTED;
+        self.flags[(pc + 1) & 0xFFF] |= EXECUTED;
+        for &addr in reads {
+            self.flags[addr as usize & 0xFFF] |= READ;
+        }
+        for &(addr, _) in writes {
+            self.flags[addr as usize & 0xFFF] |= WRITTEN;
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Executed bytes are listed as instructions, everything else byte by byte
+    fn rows(&self, memory: &[u8], range: Range<usize>) -> Vec<Row> {
+        let mut rows = Vec::new();
+        let mut addr = range.start;
+        while addr < range.end {
+            let flags = self.flags[addr];
+            if flags & EXECUTED != 0 && addr + 1 < range.end {
+                let opcode = (memory[addr] as u16) << 8 | memory[addr + 1] as u16;
+                rows.push(Row {
+                    addr,
+                    bytes: memory[addr..addr + 2].to_vec(),
+                    flags: flags | self.flags[addr + 1],
+                    text: disasm::mnemonic(opcode).unwrap_or_else(|| "???".to_string()),
+                });
+                addr += 2;
+            } else {
+                let text = match flags {
+                    0 => String::new(),
+                    _ => (0..8)
+                        .map(|b| {
+                            if memory[addr] >> (7 - b) & 1 == 1 {
+                                '█'
+                            } else {
+                                '·'
+                            }
+                        })
+                        .collect(),
+                };
+                rows.push(Row {
+                    addr,
+                    bytes: vec![memory[addr]],
+                    flags,
+                    text,
+                });
+                addr += 1;
+            }
+        }
+        rows
+    }
+
+    // Runs of bytes touched outside `range`, like code copied to RAM, stray jumps,
+    // font sprites or scratch buffers of `FX33` and `FX55`
+    fn touched_outside(&self, range: &Range<usize>) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for addr in (0..self.flags.len()).filter(|a| !range.contains(a)) {
+            if self.flags[addr] == 0 {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == addr => run.end += 1,
+                _ => runs.push(addr..addr + 1),
+            }
+        }
+        runs
+    }
+
+    fn summary(&self, range: &Range<usize>) -> String {
+        let count = |flag| range.clone().filter(|&a| self.flags[a] & flag != 0).count();
+        let untouched = range.clone().filter(|&a| self.flags[a] == 0).count();
+        let outside = |flag| {
+            (0..self.flags.len())
+                .filter(|&a| !range.contains(&a) && self.flags[a] & flag != 0)
+                .count()
+        };
+        format!(
+            "Coverage of {:#06X}-{:#06X}: {} bytes executed, {} read as data, {} written, {} untouched; \
+             outside of it {} executed, {} read, {} written",
+            range.start,
+            range.end.saturating_sub(1),
+            count(EXECUTED),
+            count(READ),
+            count(WRITTEN),
+            untouched,
+            outside(EXECUTED),
+            outside(READ),
+            outside(WRITTEN)
+        )
+    }
+
+    /// Write an annotated listing of `range` followed by the memory touched outside
+    /// of it, as HTML when the file name ends with `.html`
+    pub fn write(&self, memory: &[u8], range: Range<usize>) -> Result<String> {
+        let rows = self.rows(memory, range.clone());
+        let outside = self
+            .touched_outside(&range)
+            .into_iter()
+            .flat_map(|run| self.rows(memory, run))
+            .collect::<Vec<_>>();
+        let summary = self.summary(&range);
+        let mut out = BufWriter::new(File::create(&self.path).map_err(Error::file(&self.path))?);
+        let html = self.path.extension().is_some_and(|ext| ext == "html");
+
+        if html {
+            writeln!(
+                out,
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Coverage</title>\n\
+                 <style>\n\
+                 body {{ font-family: monospace; }}\n\
+                 td {{ padding: 0 1em; white-space: pre; }}\n\
+                 .code {{ background: #c8f0c8; }}\n\
+                 .data {{ background: #c8dcf8; }}\n\
+                 .written {{ background: #f8e0b0; }}\n\
+                 .untouched {{ color: #999; }}\n\
+                 </style>\n</head>\n<body>\n<p>{}</p>\n<table>",
+                summary
+            )?;
+        } else {
+            writeln!(out, "{}", summary)?;
+            writeln!(out, "Flags: X executed, R read as data, W written\n")?;
+        }
+        write_rows(&mut out, rows, html)?;
+        if !outside.is_empty() {
+            if html {
+                writeln!(out, "</table>\n<p>Touched outside the ROM</p>\n<table>")?;
+            } else {
+                writeln!(out, "\nTouched outside the ROM:\n")?;
+            }
+            write_rows(&mut out, outside, html)?;
+        }
+
+        if html {
+            writeln!(out, "</table>\n</body>\n</html>")?;
+        }
+        out.flush()?;
+        Ok(summary)
+    }
+}
+
+fn write_rows<W: Write>(out: &mut W, rows: Vec<Row>, html: bool) -> Result<()> {
+    for row in rows {
+        let bytes = row
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>();
+        let flags = [(EXECUTED, 'X'), (READ, 'R'), (WRITTEN, 'W')]
+            .iter()
+            .map(|&(flag, c)| if row.flags & flag != 0 { c } else { '-' })
+            .collect::<String>();
+        if html {
+            let class = match row.flags {
+                0 => "untouched",
+                f if f & EXECUTED != 0 => "code",
+                f if f & WRITTEN != 0 => "written",
+                _ => "data",
+            };
+            writeln!(
+                out,
+                "<tr class=\"{}\"><td>{:#06X}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class, row.addr, bytes, flags, row.text
+            )?;
+        } else {
+            writeln!(
+                out,
+                "{:#06X}  {:<4}  {}  {}",
+                row.addr, bytes, flags, row.text
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_separates_code_from_data() {
+        let mut memory = [0; 4096];
+        // LD I 0x206, DRW V0 V0 1, JP 0x204, sprite
+        memory[0x200..0x207].copy_from_slice(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0x3C]);
+        let mut coverage = Coverage::new("coverage.txt");
+        coverage.record(0x200, &[], &[]);
+        coverage.record(0x202, &[0x206], &[]);
+        coverage.record(0x204, &[], &[]);
+
+        let listing = coverage
+            .rows(&memory, 0x200..0x208)
+            .iter()
+            .map(|r| format!("{:#06X} {} {}", r.addr, r.flags, r.text))
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(listing, @r###"
+        0x0200 1 LD I 0x0206
+        0x0202 1 DRW V0 V0 0x01
+        0x0204 1 JP 0x0204
+        0x0206 2 ··████··
+        0x0207 0
+        "###);
+
+        // Code copied to RAM and run from there, storing BCD digits right after
+        coverage.record(0x300, &[], &[]);
+        coverage.record(0x302, &[], &[(0x304, 1), (0x305, 2), (0x306, 3)]);
+        // Reading them back, and a digit of the font
+        coverage.record(0x204, &[0x304, 0x305, 0x306, 0x0A], &[]);
+        assert_eq!(
+            coverage.touched_outside(&(0x200..0x208)),
+            vec![0x0A..0x0B, 0x300..0x307]
+        );
+        assert!(coverage.summary(&(0x200..0x208)).ends_with(
+            "1 read as data, 0 written, 1 untouched; outside of it 4 executed, 4 read, 3 written"
+        ));
+    }
+}
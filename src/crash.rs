@@ -0,0 +1,179 @@
+use crate::disasm;
+use crate::error::{format_registers, Error};
+use crate::Result;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Executed instructions kept for crash reports
+pub const HISTORY: usize = 32;
+
+/// Machine state dumped when the emulator stops on an error
+pub struct Dump<'a> {
+    pub error: &'a Error,
+    pub cycle: u64,
+    pub pc: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub dt: u8,
+    pub st: u8,
+    // Cycle, address and opcode of the last executed instructions, oldest first
+    pub history: &'a VecDeque<(u64, u16, u16)>,
+    pub memory: &'a [u8],
+    pub pixels: &'a [[u8; 64]; 32],
+}
+
+impl Dump<'_> {
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "c8 crash report\n\nerror: {}\n", self.error);
+
+        let _ = writeln!(report, "Last {} instructions", self.history.len());
+        for (k, &(cycle, addr, opcode)) in self.history.iter().enumerate() {
+            let marker = if k + 1 == self.history.len() {
+                '>'
+            } else {
+                ' '
+            };
+            let _ = writeln!(
+                report,
+                "{} {:>8}  {:#06X}  {:04X}  {}",
+                marker,
+                cycle,
+                addr,
+                opcode,
+                disasm::mnemonic(opcode).unwrap_or_else(|| "???".to_string())
+            );
+        }
+
+        let _ = writeln!(
+            report,
+            "\nRegisters after {} cycles\nPC: {:#06X}  I: {:#06X}  SP: {}  DT: {:#04X}  ST: {:#04X}",
+            self.cycle, self.pc, self.i, self.sp, self.dt, self.st
+        );
+        report.push_str(&format_registers(&self.v));
+
+        let _ = writeln!(report, "\nStack");
+        for (level, addr) in self.stack.iter().enumerate() {
+            let marker = if level == self.sp as usize { '>' } else { ' ' };
+            let _ = writeln!(report, "{} {:>2}: {:#06X}", marker, level, addr);
+        }
+
+        let _ = writeln!(report, "\nFramebuffer");
+        let border = format!("+{}+", "-".repeat(64));
+        let _ = writeln!(report, "{}", border);
+        for row in self.pixels.iter() {
+            let line = row
+                .iter()
+                .map(|&p| if p == 1 { '#' } else { ' ' })
+                .collect::<String>();
+            let _ = writeln!(report, "|{}|", line);
+        }
+        let _ = writeln!(report, "{}", border);
+
+        let _ = writeln!(report, "\nMemory");
+        report.push_str(&hexdump(self.memory));
+        report
+    }
+
+    /// Write the report to a new file in the current directory, returns its path
+    pub fn write(&self) -> Result<PathBuf> {
+        self.write_in(Path::new(""))
+    }
+
+    // Reports of the same second are numbered rather than overwritten
+    fn write_in(&self, dir: &Path) -> Result<PathBuf> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        for n in 0.. {
+            let name = match n {
+                0 => format!("c8-crash-{}.txt", secs),
+                n => format!("c8-crash-{}-{}.txt", secs, n),
+            };
+            let path = dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(self.report().as_bytes())?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Error::file(path)(e)),
+            }
+        }
+        unreachable!()
+    }
+}
+
+// 16 bytes per line, runs of lines repeating the previous one are collapsed into `*`
+fn hexdump(memory: &[u8]) -> String {
+    let mut dump = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut collapsed = false;
+    for (k, chunk) in memory.chunks(16).enumerate() {
+        if previous == Some(chunk) {
+            if !collapsed {
+                dump.push_str("*\n");
+                collapsed = true;
+            }
+            continue;
+        }
+        previous = Some(chunk);
+        collapsed = false;
+        let bytes = chunk
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(dump, "{:#06X}  {}", k * 16, bytes);
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_collapses_repeated_memory() {
+        let mut memory = [0; 96];
+        memory[0x40] = 0x12;
+        insta::assert_snapshot!(hexdump(&memory), @r###"
+        0x0000  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+        *
+        0x0040  12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+        0x0050  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+        "###);
+    }
+
+    #[test]
+    fn it_keeps_reports_of_the_same_second() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let error = Error::EmptyRom;
+        let history = VecDeque::new();
+        let dump = Dump {
+            error: &error,
+            cycle: 0,
+            pc: 0x200,
+            v: [0; 16],
+            i: 0,
+            sp: 0,
+            stack: [0; 16],
+            dt: 0,
+            st: 0,
+            history: &history,
+            memory: &[0; 4096],
+            pixels: &[[0; 64]; 32],
+        };
+        let first = dump.write_in(dir.path())?;
+        let second = dump.write_in(dir.path())?;
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+        Ok(())
+    }
+}
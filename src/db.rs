@@ -0,0 +1,235 @@
+use crate::config::Settings;
+use crate::error::Error;
+use crate::keymap::CONTROLS;
+use crate::quirks::Quirks;
+use crate::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+// Shipped with the emulator, in the format of `programs.json` from the community
+// chip-8 database so that entries can be copied from it as they are
+const BUNDLED: &str = include_str!("../db/programs.json");
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    // Keyed by SHA-1
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    // Quirks differing from the platform's, by platform id
+    #[serde(default)]
+    quirky_platforms: HashMap<String, Value>,
+    // CHIP-8 keys of the controls, by name
+    #[serde(default)]
+    keys: BTreeMap<String, Value>,
+}
+
+/// What the database knows about a ROM
+#[derive(Debug, PartialEq)]
+pub struct Metadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    // Platform id the ROM is meant for, the first one listed
+    pub platform: Option<String>,
+    // Quirks of the platform with the entry's own, by name. Only the entry's when
+    // the platform isn't known
+    pub quirks: BTreeMap<String, bool>,
+    // Instructions per frame at 60 frames per second
+    pub tickrate: Option<u32>,
+    // CHIP-8 keys of the controls of the first player, by name
+    pub controls: BTreeMap<String, u8>,
+    // Parts of the entry that couldn't be used
+    pub warnings: Vec<String>,
+}
+
+impl Metadata {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            fps: self.tickrate.map(|t| t * 60),
+            controls: self.controls.clone(),
+            quirks: self.quirks.clone(),
+            ..Settings::default()
+        }
+    }
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+}
+
+impl Database {
+    pub fn bundled() -> Result<Self> {
+        Self::parse(BUNDLED, "bundled database")
+    }
+
+    /// Read a local database in the same format
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidDatabase(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&json, &path.display().to_string())
+    }
+
+    fn parse(json: &str, name: &str) -> Result<Self> {
+        let programs = serde_json::from_str(json)
+            .map_err(|e| Error::InvalidDatabase(format!("{}: {}", name, e)))?;
+        Ok(Self { programs })
+    }
+
+    /// Add entries taking precedence over the ones already there
+    pub fn extend(&mut self, other: Database) {
+        self.programs.extend(other.programs);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<Metadata> {
+        let hash = sha1_smol::Sha1::from(rom).digest().to_string();
+        let (program, entry) = self
+            .programs
+            .iter()
+            .rev()
+            .find_map(|p| p.roms.get(&hash).map(|entry| (p, entry)))?;
+
+        let platform = entry.platforms.first().cloned();
+        let mut quirks = BTreeMap::new();
+        let mut warnings = Vec::new();
+        if let Some(id) = &platform {
+            match Quirks::platform(id) {
+                Some(platform_quirks) => quirks = platform_quirks.to_map(),
+                None => warnings.push(format!(
+                    "unknown platform {} in the ROM database, its quirks are left to the config",
+                    id
+                )),
+            }
+            if let Some(overrides) = entry.quirky_platforms.get(id) {
+                merge(&mut quirks, overrides, &mut warnings);
+            }
+        }
+        let mut controls = BTreeMap::new();
+        for (name, key) in &entry.keys {
+            // Only a player's controls have keys to press them
+            if name.starts_with("player2") {
+                continue;
+            }
+            match key.as_u64() {
+                Some(key) if key <= 0xF && CONTROLS.iter().any(|c| c.0 == name) => {
+                    controls.insert(name.clone(), key as u8);
+                }
+                _ => warnings.push(format!("ignored key {}: {} in the ROM database", name, key)),
+            }
+        }
+        Some(Metadata {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform,
+            quirks,
+            tickrate: entry.tickrate,
+            controls,
+            warnings,
+        })
+    }
+}
+
+// Set the quirks listed in `overrides`, warning about the ones that can't be
+fn merge(quirks: &mut BTreeMap<String, bool>, overrides: &Value, warnings: &mut Vec<String>) {
+    let known = Quirks::default().to_map();
+    let overrides = match overrides {
+        Value::Object(overrides) => overrides,
+        _ => {
+            warnings.push(format!("invalid quirks {} in the ROM database", overrides));
+            return;
+        }
+    };
+    for (name, quirk) in overrides {
+        match quirk.as_bool() {
+            Some(quirk) if known.contains_key(name) => {
+                quirks.insert(name.clone(), quirk);
+            }
+            _ => warnings.push(format!(
+                "ignored quirk {}: {} in the ROM database",
+                name, quirk
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_looks_up_roms_by_hash() {
+        let mut db = Database::bundled().unwrap();
+        let local = r#"[{
+            "title": "Local",
+            "authors": ["Me"],
+            "roms": {
+                "1ba58656810b67fd131eb9af3e3987863bf26c90": {
+                    "platforms": ["modernChip8"],
+                    "tickrate": 20,
+                    "quirkyPlatforms": { "modernChip8": { "jump": true } }
+                }
+            }
+        }]"#;
+        db.extend(Database::parse(local, "local").unwrap());
+        let rom = include_bytes!("../rom/IBM Logo.ch8");
+
+        let metadata = db.lookup(rom).unwrap();
+        assert_eq!(metadata.title, "Local");
+        assert_eq!(metadata.tickrate, Some(20));
+        let quirks = metadata.settings().quirks().unwrap();
+        assert!(quirks.jump);
+        assert!(!quirks.shift);
+        assert!(metadata.warnings.is_empty());
+        assert!(db.lookup(&rom[1..]).is_none());
+
+        // An entry with every field of the community database, for a platform
+        // without known quirks
+        let upstream = r##"[{
+            "title": "Upstream",
+            "description": "Every field",
+            "release": "2024",
+            "authors": ["Someone"],
+            "images": ["upstream.png"],
+            "urls": ["https://example.com"],
+            "origin": { "type": "gamejam", "reference": "Octojam 10" },
+            "copyright": "(c) 2024",
+            "license": "CC0",
+            "roms": {
+                "1ba58656810b67fd131eb9af3e3987863bf26c90": {
+                    "file": "upstream.ch8",
+                    "embeddedTitle": "UPSTREAM",
+                    "description": "Upstream ROM",
+                    "platforms": ["megachip8", "xochip"],
+                    "quirkyPlatforms": { "megachip8": { "wrap": true, "bogus": true, "jump": 1 } },
+                    "tickrate": 30,
+                    "startAddress": 512,
+                    "screenRotation": 0,
+                    "keys": { "up": 5, "down": 8, "player2Up": 1, "fire": 6 },
+                    "touchInputMode": "none",
+                    "fontStyle": "octo",
+                    "colors": { "pixels": ["#000000", "#ffffff"], "buzzer": "#ffaa00", "silence": "#000000" }
+                }
+            }
+        }]"##;
+        db.extend(Database::parse(upstream, "upstream").unwrap());
+        let metadata = db.lookup(rom).unwrap();
+        assert_eq!(metadata.platform.as_deref(), Some("megachip8"));
+        assert_eq!(metadata.quirks.len(), 1);
+        assert_eq!(metadata.quirks.get("wrap"), Some(&true));
+        assert_eq!(metadata.controls.len(), 2);
+        assert_eq!(metadata.controls.get("down"), Some(&8));
+        assert_eq!(metadata.warnings.len(), 4);
+    }
+}
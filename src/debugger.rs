@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+
+pub enum DebugCommand {
+    // Execute the next instruction
+    Next,
+    // Execute the next instruction, run a whole subroutine if it's a call
+    StepOver,
+    // Run until the current subroutine returns
+    StepOut,
+    // Run until the next breakpoint
+    Continue,
+    // Run until the cursor address is reached
+    RunToCursor,
+    // Prompt for an address and run until it is reached
+    RunToAddress,
+    // Move the cursor in the disassembly window
+    CursorUp,
+    CursorDown,
+    // Toggle a breakpoint at the cursor
+    ToggleBreakpoint,
+    // The terminal was resized, draw the debugger again
+    Resize,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    // Break before every instruction
+    Step,
+    // Run until a breakpoint is hit
+    Continue,
+    // Run until the stack pointer drops back to (or below) the level
+    Return(u8),
+    // Run until the address is reached
+    RunTo(u16),
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    mode: Mode,
+    // Selected address in the disassembly window
+    cursor: u16,
+}
+
+impl Debugger {
+    pub fn new(breakpoints: &[u16]) -> Self {
+        Self {
+            breakpoints: breakpoints.iter().copied().collect(),
+            mode: Mode::Step,
+            cursor: 0,
+        }
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    /// Whether execution should stop before the instruction at `pc`
+    pub fn should_break(&self, pc: u16, sp: u8) -> bool {
+        if self.is_breakpoint(pc) {
+            return true;
+        }
+        match self.mode {
+            Mode::Step => true,
+            Mode::Continue => false,
+            Mode::Return(level) => sp <= level,
+            Mode::RunTo(addr) => pc == addr,
+        }
+    }
+
+    /// Stop at the next instruction, with the cursor back on `pc`
+    pub fn step(&mut self, pc: u16) {
+        self.mode = Mode::Step;
+        self.cursor = pc;
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = Mode::Continue;
+    }
+
+    /// Step over the instruction at `pc`, a `2NNN` call runs until its matching return
+    pub fn step_over(&mut self, opcode: u16, sp: u8) {
+        if opcode & 0xF000 == 0x2000 {
+            self.mode = Mode::Return(sp);
+        } else {
+            self.mode = Mode::Step;
+        }
+    }
+
+    /// Run until the current subroutine returns, nothing to return from at the top level
+    pub fn step_out(&mut self, sp: u8) {
+        self.mode = match sp {
+            0 => Mode::Step,
+            _ => Mode::Return(sp - 1),
+        };
+    }
+
+    pub fn run_to(&mut self, addr: u16) {
+        self.mode = Mode::RunTo(addr);
+    }
+
+    pub fn cursor(&self) -> u16 {
+        self.cursor
+    }
+
+    pub fn move_cursor(&mut self, offset: i16) {
+        self.cursor = (self.cursor as i16 + offset * 2).clamp(0, 0x0FFE) as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_steps_over_call() {
+        let mut debugger = Debugger::new(&[]);
+        debugger.step_over(0x2300, 1);
+        // Inside the subroutine
+        assert!(!debugger.should_break(0x300, 2));
+        assert!(!debugger.should_break(0x400, 3));
+        // Returned to the caller
+        assert!(debugger.should_break(0x202, 1));
+    }
+
+    #[test]
+    fn it_steps_out_until_return() {
+        let mut debugger = Debugger::new(&[0x302]);
+        debugger.step_out(2);
+        assert!(!debugger.should_break(0x300, 2));
+        // Breakpoints still stop the run
+        assert!(debugger.should_break(0x302, 2));
+        assert!(debugger.should_break(0x208, 1));
+    }
+}
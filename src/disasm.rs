@@ -0,0 +1,130 @@
+/// Decode an opcode into its mnemonic, `None` if it's not a known instruction
+pub fn mnemonic(opcode: u16) -> Option<String> {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let text = match opcode {
+        0x00E0 => "CLS".to_string(),
+        0x00EE => "RET".to_string(),
+        _ => match opcode & 0xF000 {
+            0x1000 => format!("JP {:#06X}", nnn),
+            0x2000 => format!("CALL {:#06X}", nnn),
+            0x3000 => format!("SE V{} {:#04X}", x, nn),
+            0x4000 => format!("SNE V{} {:#04X}", x, nn),
+            0x5000 => format!("SE V{} V{}", x, y),
+            0x6000 => format!("LD V{} {:#04X}", x, nn),
+            0x7000 => format!("ADD V{} {:#04X}", x, nn),
+            0x8000 => match n {
+                0 => format!("LD V{} V{}", x, y),
+                1 => format!("OR V{} V{}", x, y),
+                2 => format!("AND V{} V{}", x, y),
+                3 => format!("XOR V{} V{}", x, y),
+                4 => format!("ADD V{} V{}", x, y),
+                5 => format!("SUB V{} V{}", x, y),
+                6 => format!("SHR V{} {{, V{}}}", x, y),
+                7 => format!("SUBN V{} V{}", x, y),
+                0xE => format!("SHL V{} {{, V{}}}", x, y),
+                _ => return None,
+            },
+            0x9000 if opcode & 1 == 0 => format!("SNE V{} V{}", x, y),
+            0xA000 => format!("LD I {:#06X}", nnn),
+            0xB000 => format!("JP V0 {:#06X}", nnn),
+            0xC000 => format!("RND V{} {:#04X}", x, nn),
+            0xD000 => format!("DRW V{} V{} {:#04X}", x, y, n),
+            0xE000 if nn == 0x9E => format!("SKP V{}", x),
+            0xE000 if nn == 0xA1 => format!("SKNP V{}", x),
+            0xF000 => match nn {
+                0x07 => format!("LD V{} DT", x),
+                0x0A => format!("LD V{} K", x),
+                0x15 => format!("LD DT V{}", x),
+                0x18 => format!("LD ST V{}", x),
+                0x1E => format!("ADD I V{}", x),
+                0x29 => format!("LD F V{}", x),
+                0x33 => format!("LD B V{}", x),
+                0x55 => format!("LD [I] V{}", x),
+                0x65 => format!("LD V{} [I]", x),
+                _ => return None,
+            },
+            _ => return None,
+        },
+    };
+    Some(text)
+}
+
+/// Static target address of a jump or call, computed jumps (`BNNN`) are excluded
+pub fn branch_target(opcode: u16) -> Option<u16> {
+    match opcode & 0xF000 {
+        0x1000 | 0x2000 => Some(opcode & 0x0FFF),
+        _ => None,
+    }
+}
+
+pub struct Line {
+    pub addr: u16,
+    pub opcode: u16,
+    pub text: String,
+}
+
+/// Disassemble `before` instructions ahead of `pc` and `after` instructions following it.
+/// Always read from current memory, so self-modifying code shows up as it is.
+pub fn window(memory: &[u8], pc: u16, before: u16, after: u16) -> Vec<Line> {
+    let start = pc.saturating_sub(before * 2);
+    let end = pc.saturating_add(after * 2);
+    let addrs = (start..=end)
+        .step_by(2)
+        .filter(|&addr| addr as usize + 1 < memory.len())
+        .collect::<Vec<_>>();
+    let targets = addrs
+        .iter()
+        .filter_map(|&addr| branch_target(read_opcode(memory, addr)))
+        .collect::<Vec<_>>();
+
+    addrs
+        .into_iter()
+        .map(|addr| {
+            let opcode = read_opcode(memory, addr);
+            let mut text = mnemonic(opcode).unwrap_or_else(|| "???".to_string());
+            if let Some(target) = branch_target(opcode) {
+                let arrow = match target {
+                    t if t == addr => '↺',
+                    t if t < addr => '↑',
+                    _ => '↓',
+                };
+                text = format!("{:<18}{}", text, arrow);
+            }
+            if targets.contains(&addr) {
+                text = format!("{:<20}◀", text);
+            }
+            Line { addr, opcode, text }
+        })
+        .collect()
+}
+
+fn read_opcode(memory: &[u8], addr: u16) -> u16 {
+    (memory[addr as usize] as u16) << 8 | memory[addr as usize + 1] as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_annotates_window() {
+        let mut memory = [0; 4096];
+        memory[0x200..0x208].copy_from_slice(&[0x00, 0xE0, 0x22, 0x06, 0x12, 0x02, 0x00, 0xEE]);
+        let lines = window(&memory, 0x202, 1, 2)
+            .iter()
+            .map(|l| format!("{:#06X} {:04X} {}", l.addr, l.opcode, l.text))
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(lines, @r###"
+        0x0200 00E0 CLS
+        0x0202 2206 CALL 0x0206       ↓ ◀
+        0x0204 1202 JP 0x0202         ↑
+        0x0206 00EE RET                 ◀
+        "###);
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::string::FromUtf8Error;
+
+/// Machine state at the point of failure
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    // Address and opcode of the failing instruction
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "PC: {:#06X}  opcode: {:#06X}  I: {:#06X}  SP: {}",
+            self.pc, self.opcode, self.i, self.sp
+        )?;
+        write!(f, "{}", format_registers(&self.v))
+    }
+}
+
+/// V0 to VF in two lines of 8
+pub fn format_registers(v: &[u8; 16]) -> String {
+    let mut registers = String::new();
+    for (x, value) in v.iter().enumerate() {
+        let separator = if x % 8 == 7 { "\n" } else { "  " };
+        let _ = write!(registers, "V{:X}: {:#04X}{}", x, value, separator);
+    }
+    registers
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownOpcode(Context),
+    // `2NNN` with every stack level in use
+    StackOverflow(Context),
+    // `00EE` with nothing to return to
+    StackUnderflow(Context),
+    MemoryOutOfRange { addr: usize, context: Context },
+    EmptyRom,
+    // No ROM file in an archive or a directory
+    NoRom(PathBuf),
+    InvalidArchive(String),
+    InvalidCartridge(String),
+    InvalidKeymap(String),
+    RomTooLarge { size: usize, max: usize },
+    // Over the font or past the end of memory
+    InvalidLoadAddress(u16),
+    InvalidTrace(String),
+    InvalidDatabase(String),
+    InvalidConfig(String),
+    // Reading or writing the file failed
+    File { path: PathBuf, source: io::Error },
+    Io(io::Error),
+    Utf8(FromUtf8Error),
+}
+
+impl Error {
+    /// Name the file at `path` in an I/O error, for `map_err`
+    pub fn file<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> Error {
+        let path = path.as_ref().to_path_buf();
+        move |source| Error::File { path, source }
+    }
+
+    /// Machine state when the error happened while executing
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            Error::UnknownOpcode(context)
+            | Error::StackOverflow(context)
+            | Error::StackUnderflow(context)
+            | Error::MemoryOutOfRange { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode(c) => {
+                write!(f, "Unknown instruction {:#06X} at {:#06X}", c.opcode, c.pc)
+            }
+            Error::StackOverflow(c) => write!(f, "Stack overflow calling from {:#06X}", c.pc),
+            Error::StackUnderflow(c) => write!(f, "Stack underflow returning from {:#06X}", c.pc),
+            Error::MemoryOutOfRange { addr, context: c } => write!(
+                f,
+                "Memory access out of range at {:#06X} by {:#06X} at {:#06X}",
+                addr, c.opcode, c.pc
+            ),
+            Error::EmptyRom => write!(f, "ROM is empty"),
+            Error::NoRom(path) => write!(f, "No ROM found in {}", path.display()),
+            Error::InvalidArchive(msg) => write!(f, "Invalid archive: {}", msg),
+            Error::InvalidCartridge(msg) => write!(f, "Invalid Octo cartridge: {}", msg),
+            Error::InvalidKeymap(msg) => write!(f, "Invalid keymap: {}", msg),
+            Error::RomTooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes, larger than the {} bytes available",
+                size, max
+            ),
+            Error::InvalidLoadAddress(addr) => write!(
+                f,
+                "Load address {:#06X} is outside of 0x0050-0x0FFF, between the font and the end of memory",
+                addr
+            ),
+            Error::InvalidTrace(msg) => write!(f, "Invalid trace: {}", msg),
+            Error::InvalidDatabase(msg) => write!(f, "Invalid ROM database: {}", msg),
+            Error::InvalidConfig(msg) => write!(f, "Invalid config: {}", msg),
+            Error::File { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Utf8(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::File { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            Error::Utf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::Utf8(e)
+    }
+}
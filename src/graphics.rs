@@ -1,76 +1,462 @@
+use crate::theme::Theme;
 use crate::Result;
 use crossterm::{
     cursor::{self, MoveTo},
-    style,
+    style::{self, Attribute, Color},
     terminal::{self, ClearType},
     ExecutableCommand, QueueableCommand,
 };
+use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::str::FromStr;
+
+/// How pixels are drawn in terminal cells
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Renderer {
+    // A cell per pixel with the theme's glyph, stretched vertically
+    Ascii,
+    // `▀`, `▄` or `█` for 2 pixels on top of each other, roughly square
+    HalfBlock,
+    // Braille patterns of 2x4 pixels
+    Braille,
+}
+
+impl FromStr for Renderer {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Renderer::Ascii),
+            "half-block" => Ok(Renderer::HalfBlock),
+            "braille" => Ok(Renderer::Braille),
+            _ => Err(format!("Unknown renderer {}", s)),
+        }
+    }
+}
+
+impl Renderer {
+    // From the largest display to the most compact
+    const ALL: [Renderer; 3] = [Renderer::Ascii, Renderer::HalfBlock, Renderer::Braille];
+
+    // Cells taken by the display, without the border
+    fn size(self) -> (usize, usize) {
+        let (width, height) = self.cell_size();
+        (64 / width, 32 / height)
+    }
+
+    // Pixels per cell across and down
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Renderer::Ascii => (1, 1),
+            Renderer::HalfBlock => (1, 2),
+            Renderer::Braille => (2, 4),
+        }
+    }
+
+    // The character of the cell at `x`, `y` in cells
+    fn cell(self, pixels: &[[u8; 64]; 32], x: usize, y: usize, glyph: char) -> char {
+        let (width, height) = self.cell_size();
+        let lit = |dx: usize, dy: usize| pixels[y * height + dy][x * width + dx] == 1;
+        match self {
+            Renderer::Ascii if lit(0, 0) => glyph,
+            Renderer::Ascii => ' ',
+            Renderer::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            },
+            Renderer::Braille => {
+                // Dots numbered down the left column then the right one, the bottom row last
+                const DOTS: [(usize, usize); 8] = [
+                    (0, 0),
+                    (0, 1),
+                    (0, 2),
+                    (1, 0),
+                    (1, 1),
+                    (1, 2),
+                    (0, 3),
+                    (1, 3),
+                ];
+                let bits = DOTS
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &(dx, dy))| lit(dx, dy))
+                    .fold(0, |bits, (i, _)| bits | 1 << i);
+                // The blank pattern doesn't take the width of a space in every font
+                match bits {
+                    0 => ' ',
+                    bits => std::char::from_u32(0x2800 + bits).unwrap(),
+                }
+            }
+        }
+    }
+}
 
 pub struct Graphics<W: Write> {
     // 64 * 32 display
     pub pixels: [[u8; 64]; 32],
     out: W,
+    // The renderer asked for and the one used, more compact when it doesn't fit
+    preferred: Renderer,
+    renderer: Renderer,
+    // Terminal cells across and down for each cell of the renderer
+    scale: usize,
+    // Top left corner of the border
+    origin: CursorPos,
+    // Columns and rows of the terminal, unknown when not drawing to one
+    terminal: Option<CursorPos>,
+    // The terminal can't fit the display, nothing is drawn
+    too_small: bool,
     debugger_layout: DebuggerLayout,
+    debugger_shown: bool,
+    theme: Theme,
+    // Shown in the top border
+    title: Option<String>,
+    // Cells as last drawn with their fade levels, only changed ones are drawn
+    // again. Empty to draw them all
+    shown: Vec<Vec<(char, u8, Option<u8>)>>,
+    // Frames left for cleared pixels to fade out
+    decay: [[u8; 64]; 32],
+    // Frames cleared pixels stay visible
+    persistence: u8,
 }
 
 impl<W: Write> Graphics<W> {
     pub fn new(mut out: W) -> Result<Self> {
-        // Draw a screen
         out.execute(terminal::Clear(terminal::ClearType::All))?;
-        out.queue(cursor::MoveTo(0, 0))?
-            .queue(style::Print("⥨".repeat(66)))?;
-        for _ in 0..32 {
-            out.queue(cursor::MoveToNextLine(1))?
+        let mut graphics = Self {
+            pixels: [[0; 64]; 32],
+            preferred: Renderer::Ascii,
+            renderer: Renderer::Ascii,
+            scale: 1,
+            origin: (0, 0),
+            terminal: None,
+            too_small: false,
+            debugger_layout: DebuggerLayout::new((0, 35)),
+            debugger_shown: false,
+            theme: Theme::default(),
+            title: None,
+            shown: Vec::new(),
+            decay: [[0; 64]; 32],
+            persistence: 0,
+            out,
+        };
+        graphics.draw_border()?;
+        Ok(graphics)
+    }
+
+    // Cells of the renderer taken by the display
+    fn size(&self) -> (usize, usize) {
+        self.renderer.size()
+    }
+
+    // Terminal cells taken by the display, without the border
+    fn screen_size(&self) -> (u16, u16) {
+        let (width, height) = self.size();
+        ((width * self.scale) as u16, (height * self.scale) as u16)
+    }
+
+    /// Redraw everything with the renderer, or a more compact one if it doesn't fit
+    pub fn set_renderer(&mut self, renderer: Renderer) -> std::io::Result<()> {
+        self.preferred = renderer;
+        self.redraw()
+    }
+
+    /// Fit the display in the terminal of the size and redraw everything
+    pub fn resize(&mut self, columns: u16, rows: u16) -> std::io::Result<()> {
+        self.terminal = Some((columns, rows));
+        self.redraw()
+    }
+
+    // Choose the renderer and the largest scale fitting the terminal, center the display
+    fn layout(&mut self) {
+        self.too_small = false;
+        match self.terminal {
+            None => {
+                self.renderer = self.preferred;
+                self.scale = 1;
+                self.origin = (0, 0);
+            }
+            Some((columns, rows)) => {
+                let below = if self.debugger_shown {
+                    DEBUGGER_ROWS
+                } else {
+                    0
+                };
+                let fits = |renderer: Renderer, scale: usize| {
+                    let (width, height) = renderer.size();
+                    width * scale + 2 <= columns as usize
+                        && height * scale + 2 + below <= rows as usize
+                };
+                let renderer = Renderer::ALL
+                    .iter()
+                    .skip_while(|&&r| r != self.preferred)
+                    .find(|&&r| fits(r, 1));
+                match renderer {
+                    Some(&renderer) => {
+                        self.renderer = renderer;
+                        self.scale = (1..).take_while(|&s| fits(renderer, s)).last().unwrap();
+                        let (width, height) = self.screen_size();
+                        self.origin = (
+                            (columns - width - 2) / 2,
+                            (rows - height - 2 - below as u16) / 2,
+                        );
+                    }
+                    None => self.too_small = true,
+                }
+            }
+        }
+        let (_, height) = self.screen_size();
+        self.debugger_layout = DebuggerLayout::new((self.origin.0, self.origin.1 + height + 3));
+    }
+
+    fn redraw(&mut self) -> std::io::Result<()> {
+        self.layout();
+        self.shown.clear();
+        self.out.queue(terminal::Clear(ClearType::All))?;
+        if self.too_small {
+            let (width, height) = Renderer::Braille.size();
+            let below = if self.debugger_shown {
+                DEBUGGER_ROWS
+            } else {
+                0
+            };
+            return self
+                .out
+                .queue(cursor::MoveTo(0, 0))?
+                .queue(style::Print(format!(
+                    "Make the terminal at least {}x{} to show the display",
+                    width + 2,
+                    height + 2 + below
+                )))?
+                .flush();
+        }
+        self.draw_border()?;
+        self.draw()?;
+        if self.debugger_shown {
+            self.draw_debugger_title()?;
+        }
+        Ok(())
+    }
+
+    // Draw a screen
+    fn draw_border(&mut self) -> std::io::Result<()> {
+        let (width, height) = self.screen_size();
+        let (x, y) = self.origin;
+        if let Some(border) = self.theme.border {
+            self.out.queue(style::SetForegroundColor(border))?;
+        }
+        self.out
+            .queue(cursor::MoveTo(x, y))?
+            .queue(style::Print("⥨".repeat(width as usize + 2)))?;
+        for row in 1..=height {
+            self.out
+                .queue(cursor::MoveTo(x, y + row))?
                 .queue(style::Print('⥮'))?
-                .queue(cursor::MoveToColumn(66))?
+                .queue(cursor::MoveTo(x + width + 1, y + row))?
                 .queue(style::Print('⥮'))?;
         }
-        out.queue(cursor::MoveToNextLine(1))?
-            .queue(style::Print("⥨".repeat(66)))?
-            .flush()?;
+        self.out
+            .queue(cursor::MoveTo(x, y + height + 1))?
+            .queue(style::Print("⥨".repeat(width as usize + 2)))?;
+        if let Some(title) = &self.title {
+            let title = format!(
+                " {} ",
+                title.chars().take(width as usize - 4).collect::<String>()
+            );
+            let title_x = (width as usize + 2 - title.chars().count()) / 2;
+            self.out
+                .queue(cursor::MoveTo(x + title_x as u16, y))?
+                .queue(style::Print(title))?;
+        }
+        if self.theme.border.is_some() {
+            self.out.queue(style::ResetColor)?;
+        }
+        self.out.flush()
+    }
 
-        Ok(Self {
-            pixels: [[0; 64]; 32],
-            debugger_layout: DebuggerLayout::new((0, 35)),
-            out,
-        })
+    pub fn clear(&mut self) {
+        self.pixels = [[0; 64]; 32];
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) -> std::io::Result<()> {
+        self.theme = theme;
+        self.redraw()
+    }
+
+    /// Keep cleared pixels visible, fading out over this many frames
+    pub fn set_persistence(&mut self, frames: u8) {
+        self.persistence = frames;
     }
 
-    pub fn clear(&mut self) -> std::io::Result<()> {
-        for mut row in self.pixels {
-            row.fill(0);
+    /// Count a frame down for pixels fading out
+    pub fn fade(&mut self) {
+        for (pixels, decay) in self.pixels.iter().zip(self.decay.iter_mut()) {
+            for (&pixel, decay) in pixels.iter().zip(decay.iter_mut()) {
+                *decay = match pixel {
+                    1 => self.persistence,
+                    _ => decay.saturating_sub(1),
+                };
+            }
         }
-        self.draw()
     }
 
+    /// Draw the cells that changed since the last time
     pub fn draw(&mut self) -> std::io::Result<()> {
-        for y in 0..32 {
-            for x in 0..64 {
-                let pixel = if self.pixels[y][x] == 1 { '*' } else { ' ' };
-                self.out
-                    .queue(cursor::MoveTo(x as u16 + 1, y as u16 + 1))?
-                    .queue(style::Print(pixel))?;
+        if self.too_small {
+            return Ok(());
+        }
+        let (width, height) = self.size();
+        let scale = self.scale;
+        let (origin_x, origin_y) = self.origin;
+        let all = self.shown.is_empty();
+        if all {
+            self.shown = vec![vec![(' ', 0, None); width]; height];
+        }
+        // Fading pixels are drawn like lit ones, in their own color
+        let mut visible = self.pixels;
+        for (row, decay) in visible.iter_mut().zip(self.decay.iter()) {
+            for (pixel, &decay) in row.iter_mut().zip(decay.iter()) {
+                *pixel |= (decay > 0) as u8;
             }
         }
+        let mut fill = Color::Reset;
+        let mut background = Color::Reset;
+        let mut drawn = false;
+        for y in 0..height {
+            let row = (0..width)
+                .map(|x| self.cell(&visible, x, y))
+                .collect::<Vec<_>>();
+            // Runs of changed cells, joined over a few unchanged ones
+            let mut runs: Vec<(usize, usize)> = Vec::new();
+            for x in (0..width).filter(|&x| all || row[x] != self.shown[y][x]) {
+                match runs.last_mut() {
+                    Some((_, end)) if x - *end <= RUN_GAP + 1 => *end = x,
+                    _ => runs.push((x, x)),
+                }
+            }
+            for (start, end) in runs {
+                if !drawn {
+                    if let Some(color) = self.theme.background {
+                        self.out.queue(style::SetBackgroundColor(color))?;
+                        background = color;
+                    }
+                }
+                drawn = true;
+                for line in 0..scale {
+                    self.out.queue(cursor::MoveTo(
+                        origin_x + 1 + (start * scale) as u16,
+                        origin_y + 1 + (y * scale + line) as u16,
+                    ))?;
+                    // Cells of a color printed together
+                    let mut cells = row[start..=end].iter().peekable();
+                    while let Some(&(cell, level, back)) = cells.next() {
+                        let color = self.fade_color(level);
+                        if color != fill {
+                            self.out.queue(style::SetForegroundColor(color))?;
+                            fill = color;
+                        }
+                        let back_color = match back {
+                            Some(level) => self.fade_color(level),
+                            None => self.theme.background.unwrap_or(Color::Reset),
+                        };
+                        if back_color != background {
+                            self.out.queue(style::SetBackgroundColor(back_color))?;
+                            background = back_color;
+                        }
+                        let mut text = cell.to_string().repeat(scale);
+                        while let Some(&&(cell, _, _)) =
+                            cells.peek().filter(|&&&(_, l, b)| (l, b) == (level, back))
+                        {
+                            text.extend(std::iter::repeat_n(cell, scale));
+                            cells.next();
+                        }
+                        self.out.queue(style::Print(text))?;
+                    }
+                }
+            }
+            self.shown[y] = row;
+        }
+        if drawn && (fill != Color::Reset || background != Color::Reset) {
+            self.out.queue(style::ResetColor)?;
+        }
         self.out.flush()
     }
 
+    // The cell at `x`, `y` with the fade levels of its foreground and, for half blocks
+    // with halves of two colors, of its background. Braille cells with lit dots leave
+    // the fading ones out as they only take one color
+    fn cell(&self, visible: &[[u8; 64]; 32], x: usize, y: usize) -> (char, u8, Option<u8>) {
+        if self.renderer == Renderer::HalfBlock {
+            let half = |py: usize| match self.pixels[py][x] {
+                1 => Some(0),
+                _ => Some(self.decay[py][x]).filter(|&decay| decay > 0),
+            };
+            if let (Some(top), Some(bottom)) = (half(y * 2), half(y * 2 + 1)) {
+                if top != bottom {
+                    return ('▀', top, Some(bottom));
+                }
+            }
+        }
+        match self.fade_level(x, y) {
+            0 => (
+                self.renderer.cell(&self.pixels, x, y, self.theme.pixel),
+                0,
+                None,
+            ),
+            level => (
+                self.renderer.cell(visible, x, y, self.theme.pixel),
+                level,
+                None,
+            ),
+        }
+    }
+
+    fn fade_color(&self, level: u8) -> Color {
+        match level {
+            0 => self.theme.fill.unwrap_or(Color::Reset),
+            level => self.theme.fade(level, self.persistence),
+        }
+    }
+
+    // Frames left for the cell fading out, 0 for cells with a lit pixel or nothing
+    fn fade_level(&self, x: usize, y: usize) -> u8 {
+        let (width, height) = self.renderer.cell_size();
+        let mut level = 0;
+        for py in y * height..(y + 1) * height {
+            for px in x * width..(x + 1) * width {
+                if self.pixels[py][px] == 1 {
+                    return 0;
+                }
+                level = level.max(self.decay[py][px]);
+            }
+        }
+        level
+    }
+
     fn cursor_move_to(pos: CursorPos) -> MoveTo {
         cursor::MoveTo(pos.0, pos.1)
     }
 
     pub fn log_op(&mut self, op: &str) -> std::io::Result<()> {
+        if self.too_small {
+            return Ok(());
+        }
         self.out
             .queue(Self::cursor_move_to(self.debugger_layout.op))?
             .queue(terminal::Clear(ClearType::UntilNewLine))?
             .queue(style::Print(op))?
             // Move cursor to the end, so that exit program will keep the whole logs
-            .queue(cursor::MoveDown(17))?
+            .queue(Self::cursor_move_to(self.debugger_layout.end))?
             .flush()
     }
 
     pub fn log_values(&mut self, registers: [u8; 16], pc: u16, vi: u16) -> std::io::Result<()> {
+        if self.too_small {
+            return Ok(());
+        }
         for (i, v) in registers.iter().enumerate() {
             self.out
                 .queue(Self::cursor_move_to(self.debugger_layout.registers[i]))?
@@ -82,11 +468,65 @@ impl<W: Write> Graphics<W> {
             .queue(Self::cursor_move_to(self.debugger_layout.vi))?
             .queue(style::Print(format!(" I: {:#06X}", vi)))?
             // Move cursor to the end, so that exit program will keep the whole logs
-            .queue(cursor::MoveDown(14))?
+            .queue(Self::cursor_move_to(self.debugger_layout.end))?
             .flush()
     }
 
+    pub fn log_disassembly(&mut self, lines: &[String], current: usize) -> std::io::Result<()> {
+        if self.too_small {
+            return Ok(());
+        }
+        let (start_x, start_y) = self.debugger_layout.disassembly;
+        for row in 0..DISASSEMBLY_LINES {
+            self.out
+                .queue(cursor::MoveTo(start_x, start_y + row as u16))?
+                .queue(terminal::Clear(ClearType::UntilNewLine))?;
+            if let Some(line) = lines.get(row) {
+                if row == current {
+                    self.out
+                        .queue(style::SetAttribute(Attribute::Reverse))?
+                        .queue(style::Print(line))?
+                        .queue(style::SetAttribute(Attribute::Reset))?;
+                } else {
+                    self.out.queue(style::Print(line))?;
+                }
+            }
+        }
+        self.out
+            .queue(Self::cursor_move_to(self.debugger_layout.end))?
+            .flush()
+    }
+
+    /// Show the title of the program in the top border, once it fits
+    pub fn show_title(&mut self, title: &str) -> std::io::Result<()> {
+        self.title = Some(title.to_string());
+        if self.too_small {
+            return Ok(());
+        }
+        self.draw_border()
+    }
+
+    /// Move the cursor below everything drawn, so output after exit doesn't overwrite it
+    pub fn move_to_end(&mut self) -> std::io::Result<()> {
+        let end = if self.too_small {
+            // Below the message
+            (0, 1)
+        } else if self.debugger_shown {
+            self.debugger_layout.end
+        } else {
+            // Below the bottom border
+            (0, self.origin.1 + self.screen_size().1 + 2)
+        };
+        self.out.queue(Self::cursor_move_to(end))?.flush()
+    }
+
+    /// Make room for the debugger below the display
     pub fn draw_debugger(&mut self) -> std::io::Result<()> {
+        self.debugger_shown = true;
+        self.redraw()
+    }
+
+    fn draw_debugger_title(&mut self) -> std::io::Result<()> {
         self.out
             .queue(Self::cursor_move_to(self.debugger_layout.start))?
             .queue(terminal::Clear(ClearType::FromCursorDown))?
@@ -95,14 +535,26 @@ impl<W: Write> Graphics<W> {
     }
 }
 
+// Unchanged cells printed again rather than moving the cursor past them, as
+// moving takes about as many bytes
+const RUN_GAP: usize = 4;
+
 type CursorPos = (u16, u16);
 
+// Instructions shown around the program counter, current one in the middle
+pub const DISASSEMBLY_LINES: usize = 21;
+
+// Rows taken by the debugger below the display, from the empty one after the border
+const DEBUGGER_ROWS: usize = DISASSEMBLY_LINES + 7;
+
 struct DebuggerLayout {
     start: CursorPos,
     registers: [CursorPos; 16],
     pc: CursorPos,
     vi: CursorPos,
     op: CursorPos,
+    disassembly: CursorPos,
+    end: CursorPos,
 }
 
 impl DebuggerLayout {
@@ -125,6 +577,9 @@ impl DebuggerLayout {
             pc: (start_x + 12, register_start_y),
             // V1: 0xFF(5 space) I: 0xFFFF
             vi: (start_x + 12, register_start_y + 1),
+            // Next to registers, V0: 0xFF   PC: 0xFFFF(4 space)
+            disassembly: (start_x + 26, register_start_y),
+            end: (start_x, register_start_y + DISASSEMBLY_LINES as u16 + 1),
         }
     }
 }
@@ -139,7 +594,241 @@ mod tests {
         let mut buffer = Vec::new();
         let out = BufWriter::new(&mut buffer);
         let _ = Graphics::new(out)?;
-        insta::assert_snapshot!(String::from_utf8(buffer)?, @"[2J[1;1H⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥮[66G⥮[1E⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨");
+        insta::assert_snapshot!(String::from_utf8(buffer)?, @"[2J[1;1H⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨[2;1H⥮[2;66H⥮[3;1H⥮[3;66H⥮[4;1H⥮[4;66H⥮[5;1H⥮[5;66H⥮[6;1H⥮[6;66H⥮[7;1H⥮[7;66H⥮[8;1H⥮[8;66H⥮[9;1H⥮[9;66H⥮[10;1H⥮[10;66H⥮[11;1H⥮[11;66H⥮[12;1H⥮[12;66H⥮[13;1H⥮[13;66H⥮[14;1H⥮[14;66H⥮[15;1H⥮[15;66H⥮[16;1H⥮[16;66H⥮[17;1H⥮[17;66H⥮[18;1H⥮[18;66H⥮[19;1H⥮[19;66H⥮[20;1H⥮[20;66H⥮[21;1H⥮[21;66H⥮[22;1H⥮[22;66H⥮[23;1H⥮[23;66H⥮[24;1H⥮[24;66H⥮[25;1H⥮[25;66H⥮[26;1H⥮[26;66H⥮[27;1H⥮[27;66H⥮[28;1H⥮[28;66H⥮[29;1H⥮[29;66H⥮[30;1H⥮[30;66H⥮[31;1H⥮[31;66H⥮[32;1H⥮[32;66H⥮[33;1H⥮[33;66H⥮[34;1H⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨⥨");
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_cells() {
+        let mut pixels = [[0; 64]; 32];
+        // A 2x4 block with its top right and bottom left pixels off
+        for (x, y) in [(0, 0), (0, 1), (1, 1), (0, 2), (1, 2), (1, 3)] {
+            pixels[y][x] = 1;
+        }
+        let cells = |renderer: Renderer, x| renderer.cell(&pixels, x, 0, '#');
+        assert_eq!(
+            (cells(Renderer::Ascii, 0), cells(Renderer::Ascii, 1)),
+            ('#', ' ')
+        );
+        assert_eq!(
+            (cells(Renderer::HalfBlock, 0), cells(Renderer::HalfBlock, 1)),
+            ('█', '▄')
+        );
+        assert_eq!(
+            (cells(Renderer::Braille, 0), cells(Renderer::Braille, 1)),
+            ('⢷', ' ')
+        );
+    }
+
+    #[test]
+    fn it_redraws_only_changed_cells() -> Result<()> {
+        let mut graphics = Graphics::new(Vec::new())?;
+        graphics.out.clear();
+        graphics.draw()?;
+        let all = graphics.out.len();
+
+        graphics.out.clear();
+        graphics.pixels[1][2] = 1;
+        graphics.pixels[1][5] = 1;
+        graphics.pixels[20][40] = 1;
+        graphics.draw()?;
+        assert_eq!(
+            String::from_utf8(graphics.out.clone()).unwrap(),
+            "\u{1b}[3;4H*  *\u{1b}[22;42H*"
+        );
+        // Bytes per frame drawing one sprite, instead of all the cells
+        assert!(graphics.out.len() * 50 < all);
+
+        graphics.out.clear();
+        graphics.draw()?;
+        assert!(graphics.out.is_empty());
+        Ok(())
+    }
+
+    // Run with `cargo test --release bytes_per_frame -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_bytes_per_frame() -> Result<()> {
+        // XOR a sprite like DXYN
+        fn sprite(pixels: &mut [[u8; 64]; 32], rows: &[u8], x: usize, y: usize) {
+            for (dy, row) in rows.iter().enumerate() {
+                for dx in 0..8 {
+                    pixels[(y + dy) % 32][(x + dx) % 64] ^= row >> (7 - dx) & 1;
+                }
+            }
+        }
+        const FRAMES: usize = 600;
+        let ball = [0xC0, 0xC0];
+        let paddle = [0x80; 6];
+        println!("renderer     full redraw  all flipped  sprite frame  time/frame");
+        for renderer in [Renderer::Ascii, Renderer::HalfBlock, Renderer::Braille] {
+            let mut graphics = Graphics::new(Vec::new())?;
+            graphics.set_renderer(renderer)?;
+            for y in 0..32 {
+                for x in 0..64 {
+                    graphics.pixels[y][x] = ((x + y) % 3 == 0) as u8;
+                }
+            }
+            // Every cell, as after a resize
+            graphics.shown.clear();
+            graphics.out.clear();
+            graphics.draw()?;
+            let full = graphics.out.len();
+
+            for row in graphics.pixels.iter_mut() {
+                row.iter_mut().for_each(|pixel| *pixel ^= 1);
+            }
+            graphics.out.clear();
+            graphics.draw()?;
+            let flipped = graphics.out.len();
+
+            // Pong: a ball crossing the screen, erased and drawn again each frame,
+            // and a paddle moving every other frame
+            graphics.pixels = [[0; 64]; 32];
+            graphics.draw()?;
+            graphics.out.clear();
+            let start = std::time::Instant::now();
+            let (mut ball_at, mut paddle_at) = ((0, 0), 0);
+            sprite(&mut graphics.pixels, &ball, ball_at.0, ball_at.1);
+            sprite(&mut graphics.pixels, &paddle, 60, paddle_at);
+            for frame in 0..FRAMES {
+                sprite(&mut graphics.pixels, &ball, ball_at.0, ball_at.1);
+                ball_at = ((ball_at.0 + 1) % 64, (ball_at.1 + 1) % 32);
+                sprite(&mut graphics.pixels, &ball, ball_at.0, ball_at.1);
+                if frame % 2 == 0 {
+                    sprite(&mut graphics.pixels, &paddle, 60, paddle_at);
+                    paddle_at = (paddle_at + 1) % 26;
+                    sprite(&mut graphics.pixels, &paddle, 60, paddle_at);
+                }
+                graphics.draw()?;
+            }
+            let elapsed = start.elapsed() / FRAMES as u32;
+            println!(
+                "{:<12} {:>11}  {:>11}  {:>12}  {:>10?}",
+                format!("{:?}", renderer),
+                full,
+                flipped,
+                graphics.out.len() / FRAMES,
+                elapsed
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_fits_the_terminal() -> Result<()> {
+        let mut graphics = Graphics::new(Vec::new())?;
+        graphics.resize(200, 80)?;
+        assert_eq!((graphics.renderer, graphics.scale), (Renderer::Ascii, 2));
+        assert_eq!(graphics.origin, (35, 7));
+        graphics.out.clear();
+        graphics.pixels[0][0] = 1;
+        graphics.draw()?;
+        assert_eq!(
+            String::from_utf8(graphics.out.clone()).unwrap(),
+            "\u{1b}[9;37H**\u{1b}[10;37H**"
+        );
+
+        // More compact renderers when it doesn't fit
+        graphics.resize(80, 24)?;
+        assert_eq!(
+            (graphics.renderer, graphics.scale),
+            (Renderer::HalfBlock, 1)
+        );
+        assert_eq!(graphics.origin, (7, 3));
+        graphics.resize(40, 12)?;
+        assert_eq!(
+            (graphics.renderer, graphics.origin),
+            (Renderer::Braille, (3, 1))
+        );
+
+        graphics.resize(20, 5)?;
+        assert!(String::from_utf8(graphics.out.clone())
+            .unwrap()
+            .ends_with("Make the terminal at least 34x10 to show the display"));
+        graphics.out.clear();
+        graphics.draw()?;
+        graphics.show_title("Pong")?;
+        graphics.log_op("n:step")?;
+        graphics.log_values([0; 16], 0x200, 0)?;
+        graphics.log_disassembly(&["0x0200".to_string()], 0)?;
+        assert!(graphics.out.is_empty());
+        // The title waits for room
+        graphics.resize(100, 40)?;
+        assert!(String::from_utf8(graphics.out.clone())
+            .unwrap()
+            .contains(" Pong "));
+
+        // Room for the debugger below
+        graphics.resize(100, 60)?;
+        graphics.draw_debugger()?;
+        assert_eq!(
+            (graphics.renderer, graphics.origin),
+            (Renderer::HalfBlock, (17, 7))
+        );
+        assert_eq!(graphics.debugger_layout.start, (17, 26));
+        Ok(())
+    }
+
+    #[test]
+    fn it_fades_cleared_pixels() -> Result<()> {
+        let mut graphics = Graphics::new(Vec::new())?;
+        graphics.set_persistence(2);
+        graphics.pixels[0][0] = 1;
+        graphics.draw()?;
+        graphics.fade();
+        graphics.clear();
+
+        // Twice as the pixel fades out, in the terminal's dark gray for lack of theme colors
+        for _ in 0..2 {
+            graphics.out.clear();
+            graphics.draw()?;
+            graphics.fade();
+            assert_eq!(
+                String::from_utf8(graphics.out.clone()).unwrap(),
+                "\u{1b}[2;2H\u{1b}[38;5;8m*\u{1b}[0m"
+            );
+        }
+        graphics.out.clear();
+        graphics.draw()?;
+        assert_eq!(
+            String::from_utf8(graphics.out.clone()).unwrap(),
+            "\u{1b}[2;2H "
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_fades_pixels_next_to_lit_ones() -> Result<()> {
+        let mut graphics = Graphics::new(Vec::new())?;
+        graphics.set_persistence(2);
+        graphics.pixels[0][0] = 1;
+        graphics.pixels[1][0] = 1;
+        graphics.fade();
+        graphics.pixels[1][0] = 0;
+        let visible = |graphics: &Graphics<Vec<u8>>| {
+            let mut visible = graphics.pixels;
+            for (row, decay) in visible.iter_mut().zip(graphics.decay.iter()) {
+                for (pixel, &decay) in row.iter_mut().zip(decay.iter()) {
+                    *pixel |= (decay > 0) as u8;
+                }
+            }
+            visible
+        };
+
+        // The lit half in the foreground, the fading one in the background
+        graphics.renderer = Renderer::HalfBlock;
+        assert_eq!(graphics.cell(&visible(&graphics), 0, 0), ('▀', 0, Some(2)));
+        graphics.draw()?;
+        assert!(String::from_utf8(graphics.out.clone())
+            .unwrap()
+            .contains("\u{1b}[48;5;8m▀"));
+
+        // Only the lit dot
+        graphics.renderer = Renderer::Braille;
+        assert_eq!(graphics.cell(&visible(&graphics), 0, 0), ('⠁', 0, None));
+        graphics.pixels[0][0] = 0;
+        assert_eq!(graphics.cell(&visible(&graphics), 0, 0), ('⠃', 2, None));
         Ok(())
     }
 }
@@ -1,75 +1,265 @@
+use crate::debugger::DebugCommand;
+use crate::keymap::Keymap;
 use crate::Result;
 use crossterm::{
-    event::{poll, read, Event, KeyCode, KeyEvent},
-    terminal,
+    event::{
+        poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, terminal,
 };
-use std::time::Duration;
+use std::io::stdout;
+use std::time::{Duration, Instant};
+
+// Without key releases from the terminal, a key is released when it hasn't been
+// pressed or repeated for this long. Terminals wait 250ms to 600ms before the first
+// repeat of a held key, which mustn't release it
+const RELEASE_TIMEOUT: Duration = Duration::from_millis(650);
 
 pub struct Keyboard {
     // Key(0-F) pressed status
     key: [bool; 16],
+    // When each key was last pressed or repeated
+    pressed_at: [Option<Instant>; 16],
+    // First key to go down since `forget_press`, repeats of a held key don't count
+    fresh: Option<u8>,
+    // Whether events are read from the terminal
+    attached: bool,
+    // Whether the terminal was asked to report key releases
+    enhanced: bool,
+    // Whether a key release was ever reported, otherwise keys are released on timeout
+    releases_reported: bool,
+    keymap: Keymap,
+    quit: bool,
+    // Latest size the terminal was resized to, not yet taken
+    resized: Option<(u16, u16)>,
 }
 
 impl Keyboard {
     pub fn new() -> Result<Self> {
         // For keyboard events to work properly
         terminal::enable_raw_mode()?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        let mut keyboard = Self::detached();
+        keyboard.attached = true;
+        keyboard.enhanced = enhanced;
+        Ok(keyboard)
+    }
 
-        Ok(Self {
+    /// Keys that are never pressed, without touching the terminal
+    pub fn detached() -> Self {
+        Self {
             key: Default::default(),
-        })
+            pressed_at: Default::default(),
+            fresh: None,
+            attached: false,
+            enhanced: false,
+            releases_reported: false,
+            keymap: Keymap::hex(),
+            quit: false,
+            resized: None,
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
     }
 
     pub fn get(&self, k: usize) -> bool {
         self.key[k]
     }
 
-    pub fn find_pressed_key(&self) -> Option<u8> {
-        self.key
-            .iter()
-            .enumerate()
-            .find(|(_, &v)| v)
-            .map(|(k, _)| k as u8)
+    /// Only keys going down from now on count as a fresh press
+    pub fn forget_press(&mut self) {
+        self.fresh = None;
+    }
+
+    pub fn fresh_press(&self) -> Option<u8> {
+        self.fresh
     }
 
+    /// Handle every pending key and resize event
     pub fn poll(&mut self) {
-        if let Ok(true) = poll(Duration::from_millis(0)) {
-            if let Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char(c),
-                modifiers: _,
-            })) = read()
-            {
-                match c {
-                    'q' => quit(),
-                    '0'..='9' | 'a'..='f' | 'A'..='F' => {
-                        let i = c.to_digit(16).unwrap();
-                        self.key.fill(false);
-                        self.key[i as usize] = true;
-                    }
-                    _ => (),
+        if !self.attached {
+            return;
+        }
+        while let Ok(true) = poll(Duration::from_millis(0)) {
+            match read() {
+                Ok(Event::Key(event)) => self.handle(event, Instant::now()),
+                Ok(Event::Resize(columns, rows)) => self.resized = Some((columns, rows)),
+                _ => {}
+            }
+        }
+        if !self.releases_reported {
+            self.release_idle(Instant::now());
+        }
+    }
+
+    fn handle(&mut self, event: KeyEvent, now: Instant) {
+        let KeyEvent { code, kind, .. } = event;
+        if kind == KeyEventKind::Release {
+            self.releases_reported = true;
+        }
+        if code == self.keymap.quit_key() {
+            self.quit |= kind == KeyEventKind::Press;
+            return;
+        }
+        if let Some(k) = self.keymap.code(code) {
+            let k = k as usize;
+            if kind == KeyEventKind::Release {
+                self.key[k] = false;
+                self.pressed_at[k] = None;
+            } else {
+                if !self.key[k] {
+                    self.fresh = self.fresh.or(Some(k as u8));
+                }
+                self.key[k] = true;
+                self.pressed_at[k] = Some(now);
+            }
+        }
+    }
+
+    fn release_idle(&mut self, now: Instant) {
+        for (key, pressed_at) in self.key.iter_mut().zip(self.pressed_at.iter_mut()) {
+            if pressed_at.is_some_and(|t| now.duration_since(t) >= RELEASE_TIMEOUT) {
+                *key = false;
+                *pressed_at = None;
+            }
+        }
+    }
+
+    /// Columns and rows of the terminal if it was resized since the last call
+    pub fn take_resize(&mut self) -> Option<(u16, u16)> {
+        self.resized.take()
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    pub fn block_until_debug_command(&mut self) -> DebugCommand {
+        loop {
+            let code = match read() {
+                Ok(Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                })) => code,
+                Ok(Event::Resize(columns, rows)) => {
+                    self.resized = Some((columns, rows));
+                    return DebugCommand::Resize;
+                }
+                _ => continue,
+            };
+            match code {
+                KeyCode::Char('n') => return DebugCommand::Next,
+                KeyCode::Char('o') => return DebugCommand::StepOver,
+                KeyCode::Char('u') => return DebugCommand::StepOut,
+                KeyCode::Char('c') => return DebugCommand::Continue,
+                KeyCode::Char('r') => return DebugCommand::RunToCursor,
+                KeyCode::Char('g') => return DebugCommand::RunToAddress,
+                KeyCode::Char('k') | KeyCode::Up => return DebugCommand::CursorUp,
+                KeyCode::Char('j') | KeyCode::Down => return DebugCommand::CursorDown,
+                KeyCode::Char('b') => return DebugCommand::ToggleBreakpoint,
+                KeyCode::Char('q') => {
+                    self.quit = true;
+                    return DebugCommand::Quit;
                 }
+                _ => (),
             }
         }
     }
 
-    pub fn block_until_press_next() {
+    /// The next key pressed, `None` when the terminal is resized first
+    pub fn block_until_key(&mut self) -> Option<KeyCode> {
         loop {
-            if let Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char(c),
-                modifiers: _,
-            })) = read()
-            {
-                match c {
-                    'n' => break,
-                    'q' => quit(),
-                    _ => (),
+            match read() {
+                Ok(Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                })) => return Some(code),
+                Ok(Event::Resize(columns, rows)) => {
+                    self.resized = Some((columns, rows));
+                    return None;
                 }
+                _ => (),
             }
         }
     }
 }
 
-fn quit() {
-    terminal::disable_raw_mode().expect("Exit raw mode");
-    std::process::exit(0)
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        if !self.attached {
+            return;
+        }
+        if self.enhanced {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyEventState, KeyModifiers};
+
+    fn event(c: char, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE,
+            kind,
+            state: KeyEventState::NONE,
+        }
+    }
+
+    #[test]
+    fn it_tracks_held_keys() {
+        let mut keyboard = Keyboard::detached();
+        let start = Instant::now();
+        keyboard.handle(event('1', KeyEventKind::Press), start);
+        keyboard.handle(event('2', KeyEventKind::Press), start);
+        assert!(keyboard.get(1) && keyboard.get(2));
+
+        // Held through the slowest repeat delay before the first repeat
+        keyboard.release_idle(start + Duration::from_millis(600));
+        assert!(keyboard.get(1) && keyboard.get(2));
+
+        // Repeats keep a key held past the timeout
+        let later = start + RELEASE_TIMEOUT;
+        keyboard.handle(event('2', KeyEventKind::Press), later);
+        keyboard.release_idle(later);
+        assert!(!keyboard.get(1) && keyboard.get(2));
+
+        keyboard.handle(event('2', KeyEventKind::Release), later);
+        assert!(!keyboard.get(2));
+        assert!(keyboard.releases_reported);
+    }
+
+    #[test]
+    fn it_reports_fresh_presses() {
+        let mut keyboard = Keyboard::detached();
+        let now = Instant::now();
+        keyboard.handle(event('1', KeyEventKind::Press), now);
+        keyboard.forget_press();
+        // Held before, repeating
+        keyboard.handle(event('1', KeyEventKind::Repeat), now);
+        assert_eq!(keyboard.fresh_press(), None);
+
+        keyboard.handle(event('a', KeyEventKind::Press), now);
+        keyboard.handle(event('b', KeyEventKind::Press), now);
+        assert_eq!(keyboard.fresh_press(), Some(0xA));
+        keyboard.handle(event('1', KeyEventKind::Release), now);
+        keyboard.handle(event('1', KeyEventKind::Press), now);
+        assert_eq!(keyboard.fresh_press(), Some(0xA));
+    }
 }
@@ -0,0 +1,178 @@
+use crate::error::Error;
+use crate::Result;
+use crossterm::event::KeyCode;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+// The CHIP-8 keypad, row by row
+const PAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
+
+const QWERTY: &str = "1234 qwer asdf zxcv";
+
+/// Controls of a ROM by their name in the community chip-8 database, and the keys
+/// pressing them on top of the layout
+pub const CONTROLS: [(&str, KeyCode); 6] = [
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("a", KeyCode::Char(' ')),
+    ("b", KeyCode::Enter),
+];
+
+/// Characters typed for each CHIP-8 key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    keys: HashMap<char, u8>,
+    // Keys of the ROM's controls
+    controls: HashMap<KeyCode, u8>,
+}
+
+impl Keymap {
+    /// Keys `0`-`9` and `a`-`f` for themselves
+    pub fn hex() -> Self {
+        let keys = (0..16)
+            .map(|k| (std::char::from_digit(k, 16).unwrap(), k as u8))
+            .collect();
+        Self {
+            keys,
+            controls: HashMap::new(),
+        }
+    }
+
+    /// The left side of a QWERTY keyboard laid out like the keypad
+    pub fn qwerty() -> Self {
+        Self::from_layout(QWERTY).unwrap()
+    }
+
+    /// 4 rows of 4 characters in the order of the keypad, `123C 456D 789E A0BF`
+    pub fn from_layout(layout: &str) -> std::result::Result<Self, String> {
+        let chars = layout
+            .split_whitespace()
+            .flat_map(|row| row.chars())
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        if chars.len() != PAD.len() {
+            return Err(format!("Expected 16 keys, found {}", chars.len()));
+        }
+        let keys = chars
+            .into_iter()
+            .zip(PAD.iter().copied())
+            .collect::<HashMap<_, _>>();
+        if keys.len() != PAD.len() {
+            return Err("A character is mapped twice".to_string());
+        }
+        Ok(Self {
+            keys,
+            controls: HashMap::new(),
+        })
+    }
+
+    /// Also press the CHIP-8 keys of the ROM's controls with the arrow keys, Space
+    /// for `a` and Enter for `b`
+    pub fn with_controls(mut self, controls: &BTreeMap<String, u8>) -> Result<Self> {
+        for (name, &key) in controls {
+            let code = CONTROLS
+                .iter()
+                .find(|(control, _)| control == name)
+                .map(|&(_, code)| code)
+                .ok_or_else(|| {
+                    let names = CONTROLS.iter().map(|c| c.0).collect::<Vec<_>>();
+                    Error::InvalidKeymap(format!(
+                        "Unknown control {}, expected one of {}",
+                        name,
+                        names.join(", ")
+                    ))
+                })?;
+            if key > 0xF {
+                return Err(Error::InvalidKeymap(format!(
+                    "Control {} on key {}, past F",
+                    name, key
+                )));
+            }
+            self.controls.insert(code, key);
+        }
+        Ok(self)
+    }
+
+    pub const PRESETS: [&'static str; 2] = ["qwerty", "hex"];
+
+    /// A preset, `qwerty` or `hex`, or a file with the layout
+    pub fn load(name: &str) -> Result<Self> {
+        match name {
+            "qwerty" => Ok(Self::qwerty()),
+            "hex" => Ok(Self::hex()),
+            path => {
+                let layout = fs::read_to_string(Path::new(path)).map_err(Error::file(path))?;
+                Self::from_layout(&layout)
+                    .map_err(|e| Error::InvalidKeymap(format!("{}: {}", path, e)))
+            }
+        }
+    }
+
+    pub fn key(&self, c: char) -> Option<u8> {
+        self.keys.get(&c.to_ascii_lowercase()).copied()
+    }
+
+    /// The CHIP-8 key pressed by a key of the layout or a control
+    pub fn code(&self, code: KeyCode) -> Option<u8> {
+        match self.controls.get(&code) {
+            Some(&key) => Some(key),
+            None => match code {
+                KeyCode::Char(c) => self.key(c),
+                _ => None,
+            },
+        }
+    }
+
+    /// `q` quits unless it's a CHIP-8 key, then Esc does
+    pub fn quit_key(&self) -> KeyCode {
+        if self.keys.contains_key(&'q') {
+            KeyCode::Esc
+        } else {
+            KeyCode::Char('q')
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_maps_layouts() {
+        let qwerty = Keymap::qwerty();
+        assert_eq!(qwerty.key('1'), Some(0x1));
+        assert_eq!(qwerty.key('4'), Some(0xC));
+        assert_eq!(qwerty.key('X'), Some(0x0));
+        assert_eq!(qwerty.key('v'), Some(0xF));
+        assert_eq!(qwerty.quit_key(), KeyCode::Esc);
+
+        let hex = Keymap::hex();
+        assert_eq!(hex.key('c'), Some(0xC));
+        assert_eq!(hex.key('q'), None);
+        assert_eq!(hex.quit_key(), KeyCode::Char('q'));
+
+        assert!(Keymap::from_layout("1234 qwer asdf zxc").is_err());
+        assert!(Keymap::from_layout("1234 qwer asdf zxcz").is_err());
+
+        let controls = [("up".to_string(), 5), ("a".to_string(), 6)]
+            .iter()
+            .cloned()
+            .collect::<BTreeMap<_, _>>();
+        let pong = Keymap::hex().with_controls(&controls).unwrap();
+        assert_eq!(pong.code(KeyCode::Up), Some(0x5));
+        assert_eq!(pong.code(KeyCode::Char(' ')), Some(0x6));
+        assert_eq!(pong.code(KeyCode::Char('5')), Some(0x5));
+        assert_eq!(pong.code(KeyCode::Down), None);
+        let mut bad = controls.clone();
+        bad.insert("fire".to_string(), 1);
+        assert!(Keymap::hex().with_controls(&bad).is_err());
+    }
+}
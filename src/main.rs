@@ -1,31 +1,344 @@
-use chip::Chip;
-use std::path::PathBuf;
-use structopt::StructOpt;
+use callgraph::CallGraph;
+use cartridge::Cartridge;
+use chip::{BoundsPolicy, Chip, UnknownPolicy};
+use config::{Config, Settings};
+use coverage::Coverage;
+use db::Database;
+use graphics::Renderer;
+use profile::Profiler;
+use rom::Platform;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use structopt::{clap, StructOpt};
+use trace::Tracer;
 
-pub type Error = Box<dyn std::error::Error>;
+pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
+mod callgraph;
+mod cartridge;
 mod chip;
+mod config;
+mod coverage;
+mod crash;
+mod db;
+mod debugger;
+mod disasm;
+mod error;
 mod graphics;
 mod keyboard;
+mod keymap;
+mod octo;
+mod profile;
+mod quirks;
+mod rom;
+mod theme;
+mod trace;
+mod tracediff;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "c8", about = "A chip-8 emulator")]
 struct Opt {
-    /// Specify FPS
-    #[structopt(short, long, default_value = "700")]
-    fps: u32,
-    /// Input file
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+    /// Specify FPS, 700 unless set in the config or the ROM database knows the speed of the ROM
+    #[structopt(short, long)]
+    fps: Option<u32>,
+    /// Input file, zip archive or directory of ROMs, Octo cartridge GIF, - for standard input
     #[structopt(parse(from_os_str))]
-    rom: PathBuf,
+    rom: Option<PathBuf>,
+    /// Variant the ROM is written for, deciding where it's loaded
+    #[structopt(long, default_value = "chip8", possible_values = &["chip8", "eti660"])]
+    platform: Platform,
+    /// Load the ROM at the address (hex) instead of the platform's
+    #[structopt(long, parse(try_from_str = parse_addr))]
+    load_address: Option<u16>,
+    /// Keys for the keypad: qwerty (1234/qwer/asdf/zxcv), hex (0-9, a-f) or a file with 4 rows of 4 keys
+    /// in keypad order (123C/456D/789E/A0BF), hex by default. Quit with Esc when q is one of them
+    #[structopt(short, long)]
+    keymap: Option<String>,
+    /// Draw a cell per pixel, 2 pixels per cell with half blocks or 2x4 with braille patterns, ascii by
+    /// default
+    #[structopt(long, possible_values = &["ascii", "half-block", "braille"])]
+    renderer: Option<Renderer>,
+    /// Screen colors: default (the terminal's), green, amber, lcd or high-contrast
+    #[structopt(long)]
+    theme: Option<String>,
+    /// Character drawn for lit pixels instead of the theme's
+    #[structopt(long)]
+    pixel: Option<char>,
+    /// Keep cleared pixels visible for the number of frames, fading out, against flicker
+    #[structopt(long)]
+    persistence: Option<u8>,
+    /// Don't look the ROM up in the database for its title, quirks and speed
+    #[structopt(long)]
+    no_db: bool,
+    /// Local ROM database in the chip-8 database programs.json format, can be repeated
+    #[structopt(long, number_of_values = 1, parse(from_os_str))]
+    db: Vec<PathBuf>,
     /// Start with debug mode
     #[structopt(short)]
     debug: bool,
+    /// Don't start with debug mode, even when the config does
+    #[structopt(long, conflicts_with = "debug")]
+    no_debug: bool,
+    /// Set a breakpoint at the address (hex), can be repeated
+    #[structopt(short, long = "break", number_of_values = 1, parse(try_from_str = parse_addr))]
+    breakpoints: Vec<u16>,
+    /// On memory accesses beyond 4K and stack overflows: wrap around like hardware (the default) or stop
+    /// with an error
+    #[structopt(long, possible_values = &["wrap", "trap"])]
+    bounds: Option<BoundsPolicy>,
+    /// On unknown instructions: stop (the default), skip them and list them on exit with the cycle
+    /// they were first met at, break into the debugger or treat `0NNN` as no-ops
+    #[structopt(
+        long,
+        possible_values = &["halt", "skip", "debug", "0nnn-as-nop"],
+        case_insensitive = true
+    )]
+    on_unknown: Option<UnknownPolicy>,
+    /// Config file to use instead of c8/config.toml in the user's config directory
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Write a trace of every executed instruction to the file
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+    /// Trace format
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    trace_format: trace::Format,
+    /// Only trace instructions within the address range (hex), e.g. 200-2FF
+    #[structopt(long, parse(try_from_str = trace::parse_range))]
+    trace_range: Option<(u16, u16)>,
+    /// Count executions per address and subroutine, print the hotspots and write them to the CSV file on exit
+    #[structopt(long, parse(from_os_str))]
+    profile: Option<PathBuf>,
+    /// Write a listing of the ROM annotated with executed, read and written bytes on exit, as HTML for *.html
+    #[structopt(long, parse(from_os_str))]
+    coverage: Option<PathBuf>,
+    /// Write the calls observed at runtime as a Graphviz DOT file on exit
+    #[structopt(long, parse(from_os_str))]
+    callgraph: Option<PathBuf>,
+    /// Write the call stacks observed at runtime as folded stacks for flame graph tools on exit
+    #[structopt(long, parse(from_os_str))]
+    folded: Option<PathBuf>,
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    let mut chip = Chip::new(opt.fps, opt.debug);
-    chip.load(opt.rom)?;
-    chip.run()
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Find the first divergence between two execution traces
+    Tracediff {
+        /// Trace to compare against
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+        /// Trace from this or another emulator
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+        /// Lines of context around the divergence
+        #[structopt(short = "C", long, default_value = "5")]
+        context: usize,
+    },
+    /// Work with the config file
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Print the configuration in effect, with the command line options given before `config`
+    Dump {
+        /// Include the database entry and config sections of this ROM
+        #[structopt(parse(from_os_str))]
+        rom: Option<PathBuf>,
+    },
+}
+
+impl Opt {
+    fn settings(&self) -> Settings {
+        Settings {
+            fps: self.fps,
+            keymap: self.keymap.clone(),
+            renderer: self.renderer,
+            theme: self.theme.clone(),
+            pixel: self.pixel,
+            persistence: self.persistence,
+            debug: match (self.debug, self.no_debug) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            bounds: self.bounds,
+            on_unknown: self.on_unknown,
+            ..Settings::default()
+        }
+    }
+}
+
+fn parse_addr(s: &str) -> std::result::Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+fn main() {
+    if let Err(e) = run(Opt::from_args()) {
+        eprintln!("error: {}", e);
+        if let Some(context) = e.context() {
+            eprint!("\n{}", context);
+        }
+        std::process::exit(1);
+    }
+}
+
+// A ROM with its title and settings from every source
+struct Program {
+    rom: Vec<u8>,
+    title: Option<String>,
+    settings: Settings,
+    // Problems with the ROM's database entry to report on exit
+    warnings: Vec<String>,
+}
+
+// Read the ROM and settle its settings: defaults, then the config, the ROM
+// database, the cartridge, the config sections of the ROM and the command line
+fn load(path: &Path, opt: &Opt, config: &Config) -> Result<Program> {
+    // Before taking over the terminal, as picking from an archive asks for the ROM
+    let cartridge = if cartridge::is_cartridge(path) {
+        Some(Cartridge::read(path)?)
+    } else {
+        None
+    };
+    // Sections of the config are by the name of the ROM picked from an archive or directory
+    let (rom, name) = match &cartridge {
+        Some(cartridge) => (
+            cartridge.rom.clone(),
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        ),
+        None => rom::read(path)?,
+    };
+    let metadata = if opt.no_db || config.no_db {
+        None
+    } else {
+        let mut db = Database::bundled()?;
+        for path in config.db.iter().chain(&opt.db) {
+            db.extend(Database::read(path)?);
+        }
+        db.lookup(&rom)
+    };
+
+    let mut settings = Settings::defaults().merge(config.settings.clone());
+    if let Some(metadata) = &metadata {
+        settings = settings.merge(metadata.settings());
+    }
+    if let Some(cartridge) = &cartridge {
+        settings = settings.merge(cartridge.settings());
+    }
+    let settings = settings
+        .merge(config.for_rom(name.as_deref(), &rom))
+        .merge(opt.settings());
+
+    let warnings = metadata
+        .as_ref()
+        .map(|metadata| metadata.warnings.clone())
+        .unwrap_or_default();
+    let title = metadata.map(|metadata| match metadata.authors.as_slice() {
+        [] => metadata.title,
+        authors => format!("{} by {}", metadata.title, authors.join(", ")),
+    });
+    Ok(Program {
+        rom,
+        title,
+        settings,
+        warnings,
+    })
+}
+
+fn run(opt: Opt) -> Result<()> {
+    // Traces are compared without the config
+    if let Some(Command::Tracediff { a, b, context }) = &opt.cmd {
+        if tracediff::run(a, b, *context)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = Config::load(opt.config.as_deref())?;
+    if let Some(Command::Config(ConfigCommand::Dump { rom })) = &opt.cmd {
+        let settings = match rom {
+            Some(rom) => load(rom, &opt, &config)?.settings,
+            None => Settings::defaults()
+                .merge(config.settings.clone())
+                .merge(opt.settings()),
+        };
+        let effective = Config {
+            settings,
+            db: config.db.iter().chain(&opt.db).cloned().collect(),
+            no_db: opt.no_db || config.no_db,
+            rom: BTreeMap::new(),
+        };
+        print!("{}", effective.dump());
+        return Ok(());
+    }
+
+    let rom = match &opt.rom {
+        Some(rom) => rom,
+        None => clap::Error::with_description(
+            "The following required arguments were not provided:\n    <rom>",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let Program {
+        rom,
+        title,
+        settings,
+        warnings,
+    } = load(rom, &opt, &config)?;
+    // Everything that can be wrong with the settings before taking over the terminal
+    let keymap = settings.keymap()?;
+    let quirks = settings.quirks()?;
+    let theme = settings.theme()?.for_terminal();
+    let tracer = match &opt.trace {
+        Some(path) => Some(Tracer::new(path, opt.trace_format, opt.trace_range)?),
+        None => None,
+    };
+
+    let fps = settings.fps.unwrap_or(config::FPS);
+    let mut chip = Chip::new(fps, settings.debug.unwrap_or(false), &opt.breakpoints)?;
+    chip.set_keymap(keymap);
+    chip.set_renderer(settings.renderer.unwrap_or(Renderer::Ascii))?;
+    let platform = opt.platform;
+    let load_address = opt.load_address.unwrap_or_else(|| platform.load_address());
+    chip.load_bytes(&rom, load_address)?;
+    for warning in warnings {
+        chip.warn(warning);
+    }
+    if let Some(title) = &title {
+        chip.set_title(title)?;
+    }
+    chip.set_quirks(quirks);
+    chip.set_theme(theme)?;
+    chip.set_persistence(settings.persistence.unwrap_or(0));
+    chip.set_bounds_policy(settings.bounds.unwrap_or(BoundsPolicy::Wrap));
+    chip.set_unknown_policy(settings.on_unknown.unwrap_or(UnknownPolicy::Halt));
+    if let Some(tracer) = tracer {
+        chip.set_tracer(tracer);
+    }
+    if let Some(path) = opt.profile {
+        chip.set_profiler(Profiler::new(path));
+    }
+    if let Some(path) = opt.coverage {
+        chip.set_coverage(Coverage::new(path));
+    }
+    if opt.callgraph.is_some() || opt.folded.is_some() {
+        chip.set_callgraph(CallGraph::new(opt.callgraph, opt.folded, load_address));
+    }
+    let result = chip.run();
+    let crash_report = result.as_ref().err().map(|e| chip.write_crash_report(e));
+    let summary = chip.finish()?;
+    // Restore the terminal before printing
+    drop(chip);
+    print!("{}", summary);
+    match crash_report {
+        Some(Ok(path)) => eprintln!("Crash report written to {}", path.display()),
+        Some(Err(e)) => eprintln!("Failed to write crash report: {}", e),
+        None => (),
+    }
+    result
 }
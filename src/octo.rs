@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+
+// Where programs are assembled to
+const START: u16 = 0x200;
+
+// Macros expanded in a program before giving up on recursion
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// Assemble Octo source to a CHIP-8 ROM. The subset covers the CHIP-8 instructions,
+/// labels, `:const`, `:alias`, `:byte`, `:org`, `:call`, `:macro`, `:calc`,
+/// `if .. then`, `if .. begin .. else .. end` and `loop .. while .. again` with `==`,
+/// `!=`, `key` and `-key` conditions. `:stringmode`, `:unpack` and the other
+/// directives, the `<`/`>` comparisons and the SUPER-CHIP and XO-CHIP instructions
+/// are reported as unsupported Octo features
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |token| (i + 1, token))
+        })
+        .collect::<Vec<_>>();
+    let mut assembler = Assembler {
+        tokens,
+        pos: 0,
+        line: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    assembler.run()
+}
+
+enum Block {
+    // Offset of the jump over the `begin` branch
+    Begin(usize),
+    // Offset of the jump over the `else` branch
+    Else(usize),
+    // Start address and offsets of the `while` jumps out
+    Loop(u16, Vec<usize>),
+}
+
+struct Macro<'a> {
+    params: Vec<&'a str>,
+    body: Vec<(usize, &'a str)>,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+    // Line of the last token read, for errors
+    line: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    // `:calc` results may be fractional
+    constants: HashMap<&'a str, f64>,
+    aliases: HashMap<&'a str, u8>,
+    // Parameters and body of each macro
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    // Instructions waiting for the address of a label, by offset in the ROM
+    fixups: Vec<(usize, &'a str, usize)>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Assembler<'a> {
+    fn run(&mut self) -> Result<Vec<u8>, String> {
+        // Execution starts at `main`, jump there unless it comes first
+        if self.tokens.get(..2).map(|t| (t[0].1, t[1].1)) != Some((":", "main")) {
+            self.jump(0x1000, "main")?;
+        }
+        while self.pos < self.tokens.len() {
+            self.statement()
+                .map_err(|e| format!("line {}: {}", self.line, e))?;
+        }
+        if !self.blocks.is_empty() {
+            return Err("Unclosed begin or loop at the end of the program".to_string());
+        }
+        for &(offset, label, line) in &self.fixups {
+            let addr = *self
+                .labels
+                .get(label)
+                .ok_or_else(|| format!("line {}: Undefined label {}", line, label))?;
+            self.rom[offset] |= (addr >> 8) as u8 & 0x0F;
+            self.rom[offset + 1] = addr as u8;
+        }
+        Ok(std::mem::take(&mut self.rom))
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let (line, token) = *self
+            .tokens
+            .get(self.pos)
+            .ok_or("Unexpected end of the program")?;
+        self.pos += 1;
+        self.line = line;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("Expected {}, found {}", expected, token)),
+        }
+    }
+
+    fn here(&self) -> u16 {
+        START + self.rom.len() as u16
+    }
+
+    fn inst(&mut self, opcode: u16) {
+        self.rom.push((opcode >> 8) as u8);
+        self.rom.push(opcode as u8);
+    }
+
+    // Instruction taking the address of a label, which may come later
+    fn jump(&mut self, opcode: u16, label: &'a str) -> Result<(), String> {
+        match self.labels.get(label) {
+            Some(&addr) => self.inst(opcode | addr),
+            None => {
+                if self.constants.contains_key(label) || number(label).is_some() {
+                    let addr = self.value(label)?;
+                    if !(0..0x1000).contains(&addr) {
+                        return Err(format!("Address out of range {}", label));
+                    }
+                    self.inst(opcode | addr as u16);
+                } else {
+                    self.fixups.push((self.rom.len(), label, self.line));
+                    self.inst(opcode);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Jump to be aimed later with `patch`, returns its offset
+    fn placeholder(&mut self) -> usize {
+        self.inst(0x1000);
+        self.rom.len() - 2
+    }
+
+    fn patch(&mut self, offset: usize, addr: u16) {
+        self.rom[offset] = 0x10 | (addr >> 8) as u8;
+        self.rom[offset + 1] = addr as u8;
+    }
+
+    fn value(&self, token: &str) -> Result<i32, String> {
+        number(token)
+            .or_else(|| self.constants.get(token).map(|&value| value.floor() as i32))
+            .or_else(|| self.labels.get(token).map(|&addr| addr as i32))
+            .ok_or_else(|| format!("Unknown value {}", token))
+    }
+
+    // Tokens between `{` and the matching `}`
+    fn braced(&mut self) -> Result<Vec<(usize, &'a str)>, String> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            tokens.push((self.line, token));
+        }
+    }
+
+    // Replace a macro call with the macro's body, the arguments in place of the parameters
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!(
+                "Too many expansions of macros, is {} recursive?",
+                name
+            ));
+        }
+        let count = self.macros[name].params.len();
+        let args = (0..count)
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let m = &self.macros[name];
+        let body = m
+            .body
+            .iter()
+            .map(
+                |&(line, token)| match m.params.iter().position(|&p| p == token) {
+                    Some(k) => (line, args[k]),
+                    None => (line, token),
+                },
+            )
+            .collect::<Vec<_>>();
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    // Octo evaluates expressions from right to left without precedence, like
+    // `2 * 3 + 1` being 8, parentheses group
+    fn calc(&self, tokens: &[&str]) -> Result<f64, String> {
+        let mut pos = 0;
+        let value = self.expression(tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("Unexpected {} in expression", token)),
+        }
+    }
+
+    fn expression(&self, tokens: &[&str], pos: &mut usize) -> Result<f64, String> {
+        let lhs = self.term(tokens, pos)?;
+        match tokens.get(*pos) {
+            None | Some(&")") => Ok(lhs),
+            Some(&op) => {
+                *pos += 1;
+                let rhs = self.expression(tokens, pos)?;
+                binary(op, lhs, rhs)
+            }
+        }
+    }
+
+    fn term(&self, tokens: &[&str], pos: &mut usize) -> Result<f64, String> {
+        let token = *tokens.get(*pos).ok_or("Unexpected end of expression")?;
+        *pos += 1;
+        match token {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(&")") => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("Expected ) in expression".to_string()),
+                }
+            }
+            "@" => {
+                let addr = self.term(tokens, pos)? as i64;
+                let offset = addr - START as i64;
+                self.rom
+                    .get(offset.max(0) as usize)
+                    .filter(|_| offset >= 0)
+                    .map(|&byte| byte as f64)
+                    .ok_or_else(|| format!("Nothing assembled at {:#06X}", addr))
+            }
+            "HERE" => Ok(self.here() as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "-" | "~" | "!" | "sin" | "cos" | "tan" | "exp" | "log" | "abs" | "sqrt" | "sign"
+            | "ceil" | "floor" => Ok(unary(token, self.term(tokens, pos)?)),
+            token => number(token)
+                .map(|n| n as f64)
+                .or_else(|| self.constants.get(token).copied())
+                .or_else(|| self.labels.get(token).map(|&addr| addr as f64))
+                .ok_or_else(|| format!("Unknown value {}", token)),
+        }
+    }
+
+    fn byte(&self, token: &str) -> Result<u16, String> {
+        match self.value(token)? {
+            value @ -128..=255 => Ok(value as u16 & 0xFF),
+            _ => Err(format!("Value out of byte range {}", token)),
+        }
+    }
+
+    fn register(&self, token: &str) -> Option<u16> {
+        if let Some(&x) = self.aliases.get(token) {
+            return Some(x as u16);
+        }
+        match token.as_bytes() {
+            [b'v' | b'V', x] => (*x as char).to_digit(16).map(|x| x as u16),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        self.register(token)
+            .ok_or_else(|| format!("Expected a register, found {}", token))
+    }
+
+    // Skip the next instruction when the condition is `skip_when` (true or false)
+    fn condition(&mut self, skip_when: bool) -> Result<(), String> {
+        let x = self.expect_register()? << 8;
+        let op = self.next()?;
+        let opcode = match (op, skip_when) {
+            ("key", false) => 0xE0A1 | x,
+            ("key", true) => 0xE09E | x,
+            ("-key", false) => 0xE09E | x,
+            ("-key", true) => 0xE0A1 | x,
+            ("==", _) | ("!=", _) => {
+                let rhs = self.next()?;
+                // Skip when equal is the same as skip when "not equal" is false
+                let skip_if_equal = (op == "==") == skip_when;
+                match (self.register(rhs), skip_if_equal) {
+                    (Some(y), true) => 0x5000 | x | y << 4,
+                    (Some(y), false) => 0x9000 | x | y << 4,
+                    (None, true) => 0x3000 | x | self.byte(rhs)?,
+                    (None, false) => 0x4000 | x | self.byte(rhs)?,
+                }
+            }
+            ("<", _) | (">", _) | ("<=", _) | (">=", _) => {
+                return Err(format!("Unsupported Octo feature, comparison {}", op))
+            }
+            _ => return Err(format!("Unsupported condition {}", op)),
+        };
+        self.inst(opcode);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        if self.macros.contains_key(token) {
+            return self.expand(token);
+        }
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name, self.here()).is_some() {
+                    return Err(format!("Label {} defined twice", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(value)?;
+                self.constants.insert(name, value as f64);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let tokens = self.braced()?;
+                let tokens = tokens.iter().map(|t| t.1).collect::<Vec<_>>();
+                let value = self.calc(&tokens)?;
+                self.constants.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut params = Vec::new();
+                while self.tokens.get(self.pos).map(|t| t.1) != Some("{") {
+                    params.push(self.next()?);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.expect_register()?;
+                self.aliases.insert(name, x as u8);
+            }
+            ":byte" => {
+                let byte = if self.tokens.get(self.pos).map(|t| t.1) == Some("{") {
+                    let tokens = self.braced()?;
+                    let tokens = tokens.iter().map(|t| t.1).collect::<Vec<_>>();
+                    let value = self.calc(&tokens)?.floor() as i32;
+                    if !(-128..=255).contains(&value) {
+                        return Err(format!("Value out of byte range {}", value));
+                    }
+                    value as u16 & 0xFF
+                } else {
+                    let value = self.next()?;
+                    self.byte(value)?
+                };
+                self.rom.push(byte as u8);
+            }
+            ":org" => {
+                let addr = self.next()?;
+                let addr = self.value(addr)?;
+                if addr < self.here() as i32 || addr >= 0x1000 {
+                    return Err(format!("Can't move to {:#06X}", addr));
+                }
+                self.rom.resize((addr as u16 - START) as usize, 0);
+            }
+            ":call" => {
+                let label = self.next()?;
+                self.jump(0x2000, label)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.inst(0x00EE),
+            "clear" => self.inst(0x00E0),
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.inst(0xF033 | x << 8);
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                // XO-CHIP ranges, `save v1 - v3`
+                if self.tokens.get(self.pos).map(|t| t.1) == Some("-") {
+                    return Err(format!("Unsupported Octo feature {} range", token));
+                }
+                let opcode = if token == "save" { 0xF055 } else { 0xF065 };
+                self.inst(opcode | x << 8);
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.next()?;
+                let n = self.value(n)?;
+                if !(0..16).contains(&n) {
+                    return Err(format!("Sprite height out of range {}", n));
+                }
+                self.inst(0xD000 | x << 8 | y << 4 | n as u16);
+            }
+            "jump" => {
+                let label = self.next()?;
+                self.jump(0x1000, label)?;
+            }
+            "jump0" => {
+                let label = self.next()?;
+                self.jump(0xB000, label)?;
+            }
+            "native" => {
+                let label = self.next()?;
+                self.jump(0x0000, label)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                let opcode = if token == "delay" { 0xF015 } else { 0xF018 };
+                self.inst(opcode | x << 8);
+            }
+            "i" => match self.next()? {
+                ":=" => match self.next()? {
+                    "hex" => {
+                        let x = self.expect_register()?;
+                        self.inst(0xF029 | x << 8);
+                    }
+                    "long" => return Err("Unsupported Octo feature i := long".to_string()),
+                    label => self.jump(0xA000, label)?,
+                },
+                "+=" => {
+                    let x = self.expect_register()?;
+                    self.inst(0xF01E | x << 8);
+                }
+                op => return Err(format!("Unsupported operation on i {}", op)),
+            },
+            "if" => {
+                let start = self.pos;
+                // Find out the form first, the condition is inverted for `begin`
+                let form = self.tokens[start..]
+                    .iter()
+                    .take(4)
+                    .map(|t| t.1)
+                    .find(|&t| t == "then" || t == "begin")
+                    .ok_or("Expected then or begin")?;
+                if form == "then" {
+                    self.condition(false)?;
+                    self.expect("then")?;
+                } else {
+                    self.condition(true)?;
+                    self.expect("begin")?;
+                    let jump = self.placeholder();
+                    self.blocks.push(Block::Begin(jump));
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin(jump)) => {
+                    let over_else = self.placeholder();
+                    self.patch(jump, self.here());
+                    self.blocks.push(Block::Else(over_else));
+                }
+                _ => return Err("else without begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin(jump)) | Some(Block::Else(jump)) => self.patch(jump, self.here()),
+                _ => return Err("end without begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop(self.here(), Vec::new())),
+            "while" => {
+                self.condition(true)?;
+                let jump = self.placeholder();
+                match self.blocks.iter_mut().rev().find_map(|b| match b {
+                    Block::Loop(_, exits) => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return Err("while outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, exits)) => {
+                    self.inst(0x1000 | start);
+                    for jump in exits {
+                        self.patch(jump, self.here());
+                    }
+                }
+                _ => return Err("again without loop".to_string()),
+            },
+            ":stringmode" | ":unpack" | ":next" | ":assert" | ":pointer" => {
+                return Err(format!("Unsupported Octo feature {}", token))
+            }
+            // SUPER-CHIP and XO-CHIP instructions
+            "hires" | "lores" | "scroll-down" | "scroll-up" | "scroll-left" | "scroll-right"
+            | "plane" | "audio" | "pitch" | "saveflags" | "loadflags" | "exit" => {
+                return Err(format!("Unsupported Octo feature {}", token))
+            }
+            _ => {
+                if let Some(x) = self.register(token) {
+                    self.register_operation(x)?;
+                } else if number(token).is_some() || self.constants.contains_key(token) {
+                    let byte = self.byte(token)?;
+                    self.rom.push(byte as u8);
+                } else if token.starts_with(':') {
+                    return Err(format!("Unknown directive {}", token));
+                } else {
+                    // A label alone calls it
+                    self.jump(0x2000, token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn register_operation(&mut self, x: u16) -> Result<(), String> {
+        let x = x << 8;
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let opcode = match (op, self.register(rhs)) {
+            (":=", Some(y)) => 0x8000 | x | y << 4,
+            ("|=", Some(y)) => 0x8001 | x | y << 4,
+            ("&=", Some(y)) => 0x8002 | x | y << 4,
+            ("^=", Some(y)) => 0x8003 | x | y << 4,
+            ("+=", Some(y)) => 0x8004 | x | y << 4,
+            ("-=", Some(y)) => 0x8005 | x | y << 4,
+            (">>=", Some(y)) => 0x8006 | x | y << 4,
+            ("=-", Some(y)) => 0x8007 | x | y << 4,
+            ("<<=", Some(y)) => 0x800E | x | y << 4,
+            (":=", None) => match rhs {
+                "key" => 0xF00A | x,
+                "delay" => 0xF007 | x,
+                "random" => {
+                    let mask = self.next()?;
+                    0xC000 | x | self.byte(mask)?
+                }
+                _ => 0x6000 | x | self.byte(rhs)?,
+            },
+            ("+=", None) => 0x7000 | x | self.byte(rhs)?,
+            ("-=", None) => 0x7000 | x | (self.byte(rhs)?.wrapping_neg() & 0xFF),
+            _ => return Err(format!("Unsupported operation {} {}", op, rhs)),
+        };
+        self.inst(opcode);
+        Ok(())
+    }
+}
+
+fn unary(op: &str, value: f64) -> f64 {
+    match op {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => (value == 0.0) as i32 as f64,
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        _ => value.floor(),
+    }
+}
+
+fn binary(op: &str, lhs: f64, rhs: f64) -> Result<f64, String> {
+    let (a, b) = (lhs as i64, rhs as i64);
+    let truth = |t: bool| t as i32 as f64;
+    Ok(match op {
+        "-" => lhs - rhs,
+        "+" => lhs + rhs,
+        "*" => lhs * rhs,
+        "/" => lhs / rhs,
+        "%" => lhs % rhs,
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => (a << b) as f64,
+        ">>" => (a >> b) as f64,
+        "pow" => lhs.powf(rhs),
+        "min" => lhs.min(rhs),
+        "max" => lhs.max(rhs),
+        "<" => truth(lhs < rhs),
+        "<=" => truth(lhs <= rhs),
+        ">" => truth(lhs > rhs),
+        ">=" => truth(lhs >= rhs),
+        "==" => truth(lhs == rhs),
+        "!=" => truth(lhs != rhs),
+        _ => return Err(format!("Unknown operator {} in expression", op)),
+    })
+}
+
+fn number(token: &str) -> Option<i32> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_assembles_octo() {
+        let source = "
+            :const SPEED 2
+            : draw # calls on its own
+              sprite v0 v1 5 ;
+            : main
+              i := hex v2
+              loop
+                v0 += SPEED
+                if v0 == 60 then v0 := 0
+                if v3 key begin draw else v1 -= 1 end
+                while v1 != 0
+              again
+              jump main
+        ";
+        let rom = assemble(source).unwrap();
+        let listing = rom
+            .chunks(2)
+            .map(|op| format!("{:02X}{:02X}", op[0], op[1]))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(
+            listing,
+            "1206 D015 00EE F229 7002 403C 6000 E39E 1216 2202 1218 71FF 4100 121E 1208 1206"
+        );
+    }
+
+    #[test]
+    fn it_expands_macros_and_calculates() {
+        let source = "
+            :macro add-twice reg n { reg += n reg += n }
+            :macro load-at reg addr { i := addr load reg }
+            : table 0x05 0x0A
+            :calc RIGHT { 2 * 3 + 1 }
+            :calc GROUPED { ( 2 * 3 ) + 1 }
+            :calc SECOND { @ ( table + 1 ) }
+            : main
+              add-twice v0 RIGHT
+              load-at v1 table
+              :byte GROUPED
+              :byte { SECOND }
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x12, 0x04, 0x05, 0x0A, 0x70, 0x08, 0x70, 0x08, 0xA2, 0x02, 0xF1, 0x65, 0x07, 0x0A
+            ]
+        );
+        assert_eq!(
+            assemble(": main :macro loop-forever { loop-forever } loop-forever"),
+            Err("line 1: Too many expansions of macros, is loop-forever recursive?".to_string())
+        );
+        assert_eq!(
+            assemble(": main if v0 < 3 then v0 := 1"),
+            Err("line 1: Unsupported Octo feature, comparison <".to_string())
+        );
+        assert_eq!(
+            assemble(": main\n  clear\n  hires"),
+            Err("line 3: Unsupported Octo feature hires".to_string())
+        );
+        assert_eq!(
+            assemble(": main scroll-left"),
+            Err("line 1: Unsupported Octo feature scroll-left".to_string())
+        );
+        assert_eq!(
+            assemble(": main i := long data : data"),
+            Err("line 1: Unsupported Octo feature i := long".to_string())
+        );
+        assert_eq!(
+            assemble(": main\n  save v0 - v3"),
+            Err("line 2: Unsupported Octo feature save range".to_string())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::chip::MAX_CALL_DEPTH;
+use crate::disasm;
+use crate::error::Error;
+use crate::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Hotspots listed in the report
+const TOP: usize = 20;
+
+#[derive(Default, Clone, Copy)]
+struct Subroutine {
+    calls: u64,
+    // Instructions executed while the subroutine is on the call stack, callees included
+    inclusive: u64,
+}
+
+pub struct Profiler {
+    path: PathBuf,
+    // Executions per address, along with the opcode last seen there
+    counts: Vec<u64>,
+    opcodes: Vec<u16>,
+    // Keyed by entry address of `2NNN` calls
+    subroutines: Vec<Subroutine>,
+    // Entry addresses of the subroutines being executed
+    stack: Vec<u16>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            counts: vec![0; 4096],
+            opcodes: vec![0; 4096],
+            subroutines: vec![Subroutine::default(); 4096],
+            stack: Vec::new(),
+            total: 0,
+        }
+    }
+
+    /// Count an executed instruction
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        let addr = pc as usize & 0xFFF;
+        self.total += 1;
+        self.counts[addr] += 1;
+        self.opcodes[addr] = opcode;
+
+        for (k, &entry) in self.stack.iter().enumerate() {
+            // Recursive calls count once
+            if !self.stack[..k].contains(&entry) {
+                self.subroutines[entry as usize].inclusive += 1;
+            }
+        }
+
+        if opcode & 0xF000 == 0x2000 {
+            let entry = opcode & 0x0FFF;
+            self.subroutines[entry as usize].calls += 1;
+            // The oldest call is lost like when the machine's stack wraps around
+            if self.stack.len() == MAX_CALL_DEPTH {
+                self.stack.remove(0);
+            }
+            self.stack.push(entry);
+        } else if opcode == 0x00EE {
+            self.stack.pop();
+        }
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.total.max(1) as f64
+    }
+
+    /// Hotspots and subroutines sorted by instructions spent
+    pub fn report(&self) -> String {
+        let mut report = format!("Profile of {} instructions\n\nHotspots\n", self.total);
+        report.push_str("  address     count       %  instruction\n");
+        let mut hotspots = (0..4096)
+            .filter(|&a| self.counts[a] > 0)
+            .collect::<Vec<_>>();
+        hotspots.sort_by_key(|&a| std::cmp::Reverse(self.counts[a]));
+        for &addr in hotspots.iter().take(TOP) {
+            report.push_str(&format!(
+                "  {:#06X}  {:>8}  {:>5.1}%  {}\n",
+                addr,
+                self.counts[addr],
+                self.percent(self.counts[addr]),
+                disasm::mnemonic(self.opcodes[addr]).unwrap_or_else(|| "???".to_string())
+            ));
+        }
+
+        report.push_str("\nSubroutines\n  entry       calls   inclusive       %\n");
+        let mut entries = (0..4096)
+            .filter(|&a| self.subroutines[a].calls > 0)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|&a| std::cmp::Reverse(self.subroutines[a].inclusive));
+        for &entry in entries.iter().take(TOP) {
+            let subroutine = self.subroutines[entry];
+            report.push_str(&format!(
+                "  {:#06X}  {:>8}  {:>10}  {:>5.1}%\n",
+                entry,
+                subroutine.calls,
+                subroutine.inclusive,
+                self.percent(subroutine.inclusive)
+            ));
+        }
+        report
+    }
+
+    /// Write every executed or called address as CSV
+    pub fn write_csv(&self) -> Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path).map_err(Error::file(&self.path))?);
+        writeln!(out, "address,executed,instruction,calls,inclusive")?;
+        for addr in 0..4096 {
+            let subroutine = self.subroutines[addr];
+            if self.counts[addr] == 0 && subroutine.calls == 0 {
+                continue;
+            }
+            let instruction = match self.counts[addr] {
+                0 => String::new(),
+                _ => disasm::mnemonic(self.opcodes[addr]).unwrap_or_else(|| "???".to_string()),
+            };
+            writeln!(
+                out,
+                "{:#06X},{},\"{}\",{},{}",
+                addr, self.counts[addr], instruction, subroutine.calls, subroutine.inclusive
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_inclusive_instructions() {
+        let mut profiler = Profiler::new("profile.csv");
+        // 0x200: CALL 0x300, 0x300: CALL 0x400, 0x400: RET, 0x302: RET, 0x202: JP 0x202
+        for &(pc, opcode) in &[
+            (0x200, 0x2300),
+            (0x300, 0x2400),
+            (0x400, 0x00EE),
+            (0x302, 0x00EE),
+            (0x202, 0x1202),
+        ] {
+            profiler.record(pc, opcode);
+        }
+        assert_eq!(profiler.subroutines[0x300].calls, 1);
+        assert_eq!(profiler.subroutines[0x300].inclusive, 3);
+        assert_eq!(profiler.subroutines[0x400].inclusive, 1);
+        assert!(profiler.stack.is_empty());
+
+        // CALL 0x200 without returning
+        for _ in 0..1000 {
+            profiler.record(0x200, 0x2200);
+        }
+        assert_eq!(profiler.stack.len(), MAX_CALL_DEPTH);
+        assert_eq!(profiler.subroutines[0x200].calls, 1000);
+        // Counted once per instruction, however deep the recursion
+        assert_eq!(profiler.subroutines[0x200].inclusive, 999);
+    }
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Behaviours that differ between CHIP-8 interpreters, named after the
+/// community chip-8 database. The defaults are what this emulator always did,
+/// apart from `FX0A` waiting for the key to be released like the original
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Quirks {
+    // `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+    // `FX55`/`FX65` increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    // `FX55`/`FX65` leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    // `BXNN` jumps to XNN + VX instead of NNN + V0
+    pub jump: bool,
+    // `DXYN` waits for the vertical blank, drawing at most a sprite per frame
+    pub vblank: bool,
+    // `8XY1`/`8XY2`/`8XY3` reset VF
+    pub logic: bool,
+    // `FX0A` completes when the key is pressed instead of when it's released
+    pub key_wait_on_press: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false,
+            key_wait_on_press: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Quirks of a platform by its id in the community chip-8 database
+    pub fn platform(id: &str) -> Option<Self> {
+        let quirks = |shift,
+                      memory_increment_by_x,
+                      memory_leave_i_unchanged,
+                      wrap,
+                      jump,
+                      vblank,
+                      logic,
+                      key_wait_on_press| Self {
+            shift,
+            memory_increment_by_x,
+            memory_leave_i_unchanged,
+            wrap,
+            jump,
+            vblank,
+            logic,
+            key_wait_on_press,
+        };
+        match id {
+            "originalChip8" | "hybridVIP" => {
+                Some(quirks(false, false, false, false, false, true, true, false))
+            }
+            "modernChip8" => Some(quirks(
+                false, false, false, false, false, false, false, false,
+            )),
+            // SUPER-CHIP 1.0 still moves I like CHIP-48, 1.1 leaves it unchanged
+            "chip48" | "superchip1" => {
+                Some(quirks(true, true, false, false, true, false, false, true))
+            }
+            "superchip" => Some(quirks(true, false, true, false, true, false, false, true)),
+            "xochip" => Some(quirks(
+                false, false, false, true, false, false, false, false,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Every quirk by name
+    pub fn to_map(self) -> BTreeMap<String, bool> {
+        serde_json::from_value(serde_json::to_value(self).unwrap()).unwrap()
+    }
+
+    /// Quirks with the ones named in `overrides` replaced
+    pub fn with(self, overrides: &BTreeMap<String, bool>) -> Result<Self, String> {
+        let mut quirks = self.to_map();
+        for (name, &quirk) in overrides {
+            match quirks.get_mut(name) {
+                Some(q) => *q = quirk,
+                None => return Err(format!("Unknown quirk {}", name)),
+            }
+        }
+        serde_json::from_value(serde_json::to_value(quirks).unwrap()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_knows_platform_quirks() {
+        let schip10 = Quirks::platform("superchip1").unwrap();
+        assert!(schip10.memory_increment_by_x && !schip10.memory_leave_i_unchanged);
+        assert_eq!(Quirks::platform("chip48"), Some(schip10));
+        let schip11 = Quirks::platform("superchip").unwrap();
+        assert!(!schip11.memory_increment_by_x && schip11.memory_leave_i_unchanged);
+        assert!(Quirks::platform("megachip8").is_none());
+    }
+}
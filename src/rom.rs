@@ -0,0 +1,264 @@
+use crate::error::Error;
+use crate::Result;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// Programs go after the font and can't go past 4K of memory
+const FONT_END: usize = 0x50;
+const MEMORY_END: usize = 0x1000;
+
+// Extensions of ROM files picked from archives and directories
+const EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+/// CHIP-8 variant a program is written for, which decides where it's loaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Chip8,
+    // ETI-660 programs start at 0x600
+    Eti660,
+}
+
+impl Platform {
+    pub fn load_address(self) -> u16 {
+        match self {
+            Platform::Chip8 => 0x200,
+            Platform::Eti660 => 0x600,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "chip8" => Ok(Platform::Chip8),
+            "eti660" => Ok(Platform::Eti660),
+            _ => Err(format!("Unknown platform {}", s)),
+        }
+    }
+}
+
+/// Read a whole ROM file, `-` reads standard input. From a zip archive or a directory
+/// the ROM file in it is read, the user picks one when there are several. Returns the
+/// ROM with the name of the file read, none for standard input
+pub fn read<P: AsRef<Path>>(path: P) -> Result<(Vec<u8>, Option<String>)> {
+    read_from(path.as_ref(), io::stdin().lock())
+}
+
+// Standard input being `input`
+fn read_from<R: BufRead>(path: &Path, mut input: R) -> Result<(Vec<u8>, Option<String>)> {
+    let mut rom = Vec::new();
+    let chosen;
+    if path == Path::new("-") {
+        input.read_to_end(&mut rom)?;
+        return Ok((rom, None));
+    } else if path.is_dir() {
+        let mut names = fs::read_dir(path)
+            .map_err(Error::file(path))?
+            .map(|entry| {
+                Ok(entry
+                    .map_err(Error::file(path))?
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        names.retain(|name| is_rom(name) && path.join(name).is_file());
+        names.sort();
+        let name = choose(path, &names, input)?;
+        let file = path.join(name);
+        File::open(&file)
+            .and_then(|mut f| f.read_to_end(&mut rom))
+            .map_err(Error::file(file))?;
+        chosen = Some(name.to_string());
+    } else if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        let file = File::open(path).map_err(Error::file(path))?;
+        let mut archive = ZipArchive::new(file).map_err(|e| zip_error(e, path))?;
+        let mut names = archive
+            .file_names()
+            .filter(|name| is_rom(name))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        names.sort();
+        let name = choose(path, &names, input)?;
+        archive
+            .by_name(name)
+            .map_err(|e| zip_error(e, path))?
+            .read_to_end(&mut rom)
+            .map_err(Error::file(path))?;
+        // Without the folders in the archive
+        chosen = Path::new(name)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned());
+    } else {
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut rom))
+            .map_err(Error::file(path))?;
+        chosen = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    }
+    Ok((rom, chosen))
+}
+
+fn is_rom(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|ext| EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+fn zip_error(e: ZipError, path: &Path) -> Error {
+    match e {
+        ZipError::Io(e) => Error::file(path)(e),
+        e => Error::InvalidArchive(e.to_string()),
+    }
+}
+
+// The only ROM, or the one picked from a numbered list by a line of `input`
+fn choose<'a, R: BufRead>(path: &Path, names: &'a [String], input: R) -> Result<&'a str> {
+    match names {
+        [] => return Err(Error::NoRom(path.to_path_buf())),
+        [name] => return Ok(name),
+        _ => (),
+    }
+    eprintln!("ROMs in {}:", path.display());
+    for (k, name) in names.iter().enumerate() {
+        eprintln!("{:>4}  {}", k + 1, name);
+    }
+    let mut lines = input.lines();
+    loop {
+        eprint!("Choose a ROM [1-{}]: ", names.len());
+        io::stderr().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Err(Error::NoRom(path.to_path_buf())),
+        };
+        match line.trim().parse::<usize>() {
+            Ok(k) if (1..=names.len()).contains(&k) => return Ok(&names[k - 1]),
+            _ => (),
+        }
+    }
+}
+
+/// Check that a ROM fits in memory from `load_address`, returns the bytes to load
+/// along with a warning when trailing zero padding beyond memory was dropped
+pub fn fit(rom: &[u8], load_address: u16) -> Result<(&[u8], Option<String>)> {
+    if !(FONT_END..MEMORY_END).contains(&(load_address as usize)) {
+        return Err(Error::InvalidLoadAddress(load_address));
+    }
+    if rom.is_empty() {
+        return Err(Error::EmptyRom);
+    }
+    let max = MEMORY_END.saturating_sub(load_address as usize);
+    if rom.len() <= max {
+        return Ok((rom, None));
+    }
+    if rom[max..].iter().any(|&b| b != 0) {
+        return Err(Error::RomTooLarge {
+            size: rom.len(),
+            max,
+        });
+    }
+    let warning = format!(
+        "ignored {} bytes of zero padding past the end of memory",
+        rom.len() - max
+    );
+    Ok((&rom[..max], Some(warning)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_fits_roms_per_load_address() {
+        let rom = vec![0x12; 0xA01];
+        assert!(fit(&rom, Platform::Chip8.load_address()).is_ok());
+        assert!(matches!(
+            fit(&rom, Platform::Eti660.load_address()),
+            Err(Error::RomTooLarge {
+                size: 0xA01,
+                max: 0xA00
+            })
+        ));
+
+        let mut padded = vec![0x12; 0xA00];
+        padded.extend_from_slice(&[0; 0x10]);
+        let (program, warning) = fit(&padded, Platform::Eti660.load_address()).unwrap();
+        assert_eq!(program.len(), 0xA00);
+        assert!(warning.is_some());
+
+        assert!(matches!(fit(&[], 0x200), Err(Error::EmptyRom)));
+
+        // Over the font or past the end of memory
+        for load_address in [0x4F, 0x1000, 0x2000] {
+            assert!(matches!(
+                fit(&[0x12, 0, 0], load_address),
+                Err(Error::InvalidLoadAddress(address)) if address == load_address
+            ));
+        }
+        assert!(fit(&[0x12], 0x50).is_ok() && fit(&[0x12], 0xFFF).is_ok());
+    }
+
+    // A zip archive of the files with their contents
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn it_picks_roms_from_archives_and_directories() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let read = |path: &Path, input: &str| read_from(path, input.as_bytes()).map(|r| r.0);
+        let name = |path: &Path, input: &str| read_from(path, input.as_bytes()).map(|r| r.1);
+
+        // The only ROM, whatever else is around
+        let single = dir.path().join("single.zip");
+        fs::write(
+            &single,
+            zip(&[("README.txt", b"hi"), ("games/pong.ch8", b"\x12\x00")]),
+        )?;
+        assert_eq!(read(&single, "")?, b"\x12\x00");
+        assert_eq!(name(&single, "")?.as_deref(), Some("pong.ch8"));
+
+        // Picked by index, asking again after a bad or out of range answer
+        let several = dir.path().join("several.ZIP");
+        fs::write(&several, zip(&[("b.c8", b"b"), ("a.ch8", b"a")]))?;
+        assert_eq!(read(&several, "x\n3\n 2 \n")?, b"b");
+        assert!(matches!(read(&several, "0\n"), Err(Error::NoRom(_))));
+        assert!(matches!(read(&several, ""), Err(Error::NoRom(_))));
+
+        let roms = dir.path().join("roms");
+        fs::create_dir_all(roms.join("sub.ch8"))?;
+        fs::write(roms.join("notes.txt"), "")?;
+        assert!(matches!(read(&roms, "1\n"), Err(Error::NoRom(_))));
+        fs::write(roms.join("tetris.sc8"), "t")?;
+        fs::write(roms.join("blitz.xo8"), "x")?;
+        assert_eq!(read(&roms, "1\n")?, b"x");
+        assert_eq!(name(&roms, "2\n")?.as_deref(), Some("tetris.sc8"));
+
+        let missing = dir.path().join("missing.ch8");
+        let error = read(&missing, "").unwrap_err().to_string();
+        assert!(error.starts_with(&format!("{}: ", missing.display())));
+
+        assert_eq!(read(Path::new("-"), "stdin")?, b"stdin");
+        assert_eq!(name(Path::new("-"), "stdin")?, None);
+        assert_eq!(
+            name(&roms.join("tetris.sc8"), "")?.as_deref(),
+            Some("tetris.sc8")
+        );
+        Ok(())
+    }
+}
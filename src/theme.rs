@@ -0,0 +1,211 @@
+use crossterm::style::Color;
+use std::convert::TryFrom;
+use std::env;
+
+pub const NAMES: &[&str] = &["default", "green", "amber", "lcd", "high-contrast"];
+
+// Levels of the 6x6x6 color cube in the 256 color palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Colors of the screen and the glyph of lit pixels, the terminal's colors when not set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub fill: Option<Color>,
+    pub background: Option<Color>,
+    pub border: Option<Color>,
+    pub pixel: char,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            fill: None,
+            background: None,
+            border: None,
+            pixel: '*',
+        }
+    }
+}
+
+impl Theme {
+    /// A built-in theme
+    pub fn named(name: &str) -> Option<Self> {
+        let theme = |fill, background, border| Self {
+            fill: Some(rgb(fill)),
+            background: Some(rgb(background)),
+            border: Some(rgb(border)),
+            pixel: '█',
+        };
+        match name {
+            "default" => Some(Self::default()),
+            // Green phosphor of early monitors
+            "green" => Some(theme(0x33FF66, 0x0A140C, 0x1F8F3F)),
+            "amber" => Some(theme(0xFFB000, 0x1A1000, 0x9F6E00)),
+            // Greenish LCD of handhelds
+            "lcd" => Some(theme(0x0F380F, 0x9BBC0F, 0x306230)),
+            "high-contrast" => Some(theme(0xFFFFFF, 0x000000, 0xFFFFFF)),
+            _ => None,
+        }
+    }
+
+    /// Color of a pixel fading out with `level` of `levels` frames left, from the
+    /// pixel color to the background
+    pub fn fade(&self, level: u8, levels: u8) -> Color {
+        let (fill, background) = match (self.fill, self.background) {
+            (Some(fill), Some(background)) => (fill, background),
+            _ => return Color::DarkGrey,
+        };
+        let (f, b) = match (to_rgb(fill), to_rgb(background)) {
+            (Some(f), Some(b)) => (f, b),
+            _ => return Color::DarkGrey,
+        };
+        let t = level as f32 / (levels as f32 + 1.0);
+        let mix = |f: u8, b: u8| (b as f32 + (f as f32 - b as f32) * t).round() as u8;
+        let color = Color::Rgb {
+            r: mix(f.0, b.0),
+            g: mix(f.1, b.1),
+            b: mix(f.2, b.2),
+        };
+        match fill {
+            Color::AnsiValue(_) => to_256_color(color),
+            _ => color,
+        }
+    }
+
+    /// The theme with RGB colors replaced by the closest of the 256 color palette,
+    /// unless the terminal announces true color support
+    pub fn for_terminal(self) -> Self {
+        let truecolor = env::var("COLORTERM")
+            .map(|v| v == "truecolor" || v == "24bit")
+            .unwrap_or(false);
+        if truecolor {
+            self
+        } else {
+            self.with_256_colors()
+        }
+    }
+
+    fn with_256_colors(self) -> Self {
+        let convert = |color: Option<Color>| color.map(to_256_color);
+        Self {
+            fill: convert(self.fill),
+            background: convert(self.background),
+            border: convert(self.border),
+            pixel: self.pixel,
+        }
+    }
+}
+
+/// A color written `#RRGGBB`, as an index in the 256 color palette or by name
+pub fn parse_color(color: &str) -> Option<Color> {
+    if let Some(digits) = color.strip_prefix('#') {
+        if digits.len() != 6 {
+            return None;
+        }
+        return u32::from_str_radix(digits, 16).ok().map(rgb);
+    }
+    match color.parse::<u8>() {
+        Ok(index) => Some(Color::AnsiValue(index)),
+        Err(_) => Color::try_from(color).ok(),
+    }
+}
+
+pub fn format_color(color: Color) -> String {
+    match color {
+        Color::Rgb { r, g, b } => format!("#{:02X}{:02X}{:02X}", r, g, b),
+        Color::AnsiValue(index) => index.to_string(),
+        // Names as parsed, `DarkGreen` as `dark_green`
+        color => format!("{:?}", color)
+            .chars()
+            .enumerate()
+            .flat_map(|(i, c)| match c.is_uppercase() && i > 0 {
+                true => vec!['_', c.to_ascii_lowercase()],
+                false => vec![c.to_ascii_lowercase()],
+            })
+            .collect(),
+    }
+}
+
+fn rgb(rgb: u32) -> Color {
+    Color::Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+// Components of an RGB color or one of the cube or the gray ramp
+fn to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    match color {
+        Color::Rgb { r, g, b } => Some((r, g, b)),
+        Color::AnsiValue(i @ 16..=231) => {
+            let i = i as usize - 16;
+            Some((CUBE[i / 36], CUBE[i / 6 % 6], CUBE[i % 6]))
+        }
+        Color::AnsiValue(i @ 232..=255) => {
+            let gray = 8 + (i - 232) * 10;
+            Some((gray, gray, gray))
+        }
+        _ => None,
+    }
+}
+
+// The closest color of the cube or the gray ramp
+fn to_256_color(color: Color) -> Color {
+    let (r, g, b) = match color {
+        Color::Rgb { r, g, b } => (r, g, b),
+        color => return color,
+    };
+    let level = |c: u8| {
+        (0..CUBE.len())
+            .min_by_key(|&i| (CUBE[i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (CUBE[ri], CUBE[gi], CUBE[bi]);
+    // Grays from 8 to 238 in steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + gray * 10;
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(cr, r) + d(cg, g) + d(cb, b)
+    };
+    if distance((gray_level, gray_level, gray_level)) < distance(cube) {
+        Color::AnsiValue(232 + gray)
+    } else {
+        Color::AnsiValue(16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_falls_back_with_256_colors() {
+        for name in NAMES {
+            assert!(Theme::named(name).is_some());
+        }
+        assert_eq!(Theme::named("default"), Some(Theme::default()));
+
+        let lcd = Theme::named("lcd").unwrap().with_256_colors();
+        assert_eq!(lcd.pixel, '█');
+        assert_eq!(lcd.fill, Some(Color::AnsiValue(234)));
+        assert_eq!(lcd.background, Some(Color::AnsiValue(106)));
+        assert_eq!(to_256_color(rgb(0xFF0000)), Color::AnsiValue(196));
+        assert_eq!(to_256_color(rgb(0x808080)), Color::AnsiValue(244));
+        assert_eq!(to_256_color(Color::Red), Color::Red);
+
+        let amber = Theme::named("amber").unwrap();
+        assert_eq!(amber.fade(3, 3), rgb(0xC68800));
+        assert_eq!(Theme::default().fade(1, 3), Color::DarkGrey);
+        assert_eq!(lcd.fade(1, 1), Color::AnsiValue(58));
+
+        assert_eq!(parse_color("#FF0000"), Some(rgb(0xFF0000)));
+        assert_eq!(parse_color("208"), Some(Color::AnsiValue(208)));
+        assert_eq!(parse_color("dark_green"), Some(Color::DarkGreen));
+        assert_eq!(parse_color("#FF00"), None);
+        assert_eq!(format_color(Color::DarkGreen), "dark_green");
+    }
+}
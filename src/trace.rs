@@ -0,0 +1,150 @@
+use crate::error::Error;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // One aligned line per instruction
+    Text,
+    // JSON Lines, one `Record` object per line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown trace format {}", s)),
+        }
+    }
+}
+
+/// State after one executed instruction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub cycle: u64,
+    // Address and opcode of the executed instruction
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub v: [u8; 16],
+    // Changed registers as (register, old, new)
+    pub changed: Vec<(u8, u8, u8)>,
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    // Memory written as (address, value)
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl Record {
+    /// Registers that differ between `before` and `after`
+    pub fn changed(before: &[u8; 16], after: &[u8; 16]) -> Vec<(u8, u8, u8)> {
+        (0..16)
+            .filter(|&x| before[x] != after[x])
+            .map(|x| (x as u8, before[x], after[x]))
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        let mut line = format!(
+            "{:>8}  {:04X}  {:04X}  {:<18} I={:04X} DT={:02X} ST={:02X}",
+            self.cycle, self.pc, self.opcode, self.mnemonic, self.i, self.dt, self.st
+        );
+        for (x, old, new) in &self.changed {
+            line.push_str(&format!("  V{:X}={:02X}>{:02X}", x, old, new));
+        }
+        for (addr, value) in &self.writes {
+            line.push_str(&format!("  [{:04X}]={:02X}", addr, value));
+        }
+        line
+    }
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: Format,
+    // Only trace instructions within the (inclusive) address range
+    range: Option<(u16, u16)>,
+}
+
+impl Tracer {
+    pub fn new<P: AsRef<Path>>(path: P, format: Format, range: Option<(u16, u16)>) -> Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(&path).map_err(Error::file(path))?),
+            format,
+            range,
+        })
+    }
+
+    pub fn trace(&mut self, record: &Record) -> Result<()> {
+        if let Some((start, end)) = self.range {
+            if record.pc < start || record.pc > end {
+                return Ok(());
+            }
+        }
+        match self.format {
+            Format::Text => writeln!(self.out, "{}", record.text())?,
+            Format::Json => {
+                let json = serde_json::to_string(record).map_err(std::io::Error::from)?;
+                writeln!(self.out, "{}", json)?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Parse an inclusive address range like `200-2FF`
+pub fn parse_range(s: &str) -> std::result::Result<(u16, u16), String> {
+    let parse = |s: &str| {
+        u16::from_str_radix(s.trim().trim_start_matches("0x"), 16)
+            .map_err(|e| format!("Invalid address {}: {}", s, e))
+    };
+    match s.split_once('-') {
+        Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+        None => Err(format!("Invalid range {}, expected START-END", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_text_line() {
+        let mut v = [0; 16];
+        v[0xA] = 2;
+        let record = Record {
+            cycle: 42,
+            pc: 0x204,
+            opcode: 0xFA33,
+            mnemonic: "LD B V10".to_string(),
+            v,
+            changed: Record::changed(&[0; 16], &v),
+            i: 0x300,
+            dt: 0,
+            st: 3,
+            writes: vec![(0x300, 0), (0x301, 0), (0x302, 2)],
+        };
+        insta::assert_snapshot!(record.text(), @"      42  0204  FA33  LD B V10           I=0300 DT=00 ST=03  VA=00>02  [0300]=00  [0301]=00  [0302]=02");
+    }
+
+    #[test]
+    fn it_parses_range() {
+        assert_eq!(parse_range("200-2FF"), Ok((0x200, 0x2FF)));
+        assert_eq!(parse_range("0x200-0x2ff"), Ok((0x200, 0x2FF)));
+        assert!(parse_range("200").is_err());
+    }
+}
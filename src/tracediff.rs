@@ -0,0 +1,348 @@
+use crate::error::Error;
+use crate::trace::Record;
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// One traced instruction, fields a trace doesn't carry are `None` and never compared
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: Option<u16>,
+    pub v: Option<[u8; 16]>,
+    pub i: Option<u16>,
+    pub writes: Option<Vec<(u16, u8)>>,
+    // Line number and text in the trace file, for context
+    pub line_no: usize,
+    pub line: String,
+}
+
+/// Read a trace in any of the supported formats:
+///
+/// - JSON Lines written by `--trace-format json`
+/// - text written by `--trace-format text`, registers are rebuilt from the changes
+///   and only compared while the trace runs without gaps from the first cycle
+/// - a simple format for other emulators, one instruction per line as whitespace
+///   separated `key=value` fields in hex: `pc` (required), `cycle`, `op`, `i`,
+///   `v0`..`vf` and `w=ADDR:VALUE,...` memory writes. Without `cycle` the line
+///   count is taken, lines starting with `#` are ignored
+pub fn read_trace<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(Error::file(path))?;
+    let mut entries = Vec::new();
+    let mut v = [0; 16];
+    // Last cycle of a text trace while it has no gaps, the registers are known
+    let mut gapless = Some(0);
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let line_no = i + 1;
+        let parsed = if trimmed.starts_with('{') {
+            parse_json(trimmed)
+        } else if !trimmed
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .contains('=')
+        {
+            parse_text(trimmed, &mut v).map(|mut entry| {
+                gapless = gapless
+                    .filter(|&last| entry.cycle == last + 1)
+                    .map(|_| entry.cycle);
+                if gapless.is_none() {
+                    // Filtered or started later, the changes don't tell the registers
+                    entry.v = None;
+                }
+                entry
+            })
+        } else {
+            parse_simple(trimmed, entries.len() as u64 + 1)
+        };
+        let mut entry = parsed
+            .map_err(|e| Error::InvalidTrace(format!("{}:{}: {}", path.display(), line_no, e)))?;
+        entry.line_no = line_no;
+        entry.line = line.to_string();
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_json(line: &str) -> std::result::Result<Entry, String> {
+    let record: Record = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    Ok(Entry {
+        cycle: record.cycle,
+        pc: record.pc,
+        opcode: Some(record.opcode),
+        v: Some(record.v),
+        i: Some(record.i),
+        writes: Some(record.writes),
+        ..Default::default()
+    })
+}
+
+fn hex(s: &str) -> std::result::Result<u16, String> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("Invalid hex value {}: {}", s, e))
+}
+
+// `cycle pc opcode mnemonic.. I=.. DT=.. ST=.. Vx=old>new.. [addr]=value..`
+fn parse_text(line: &str, v: &mut [u8; 16]) -> std::result::Result<Entry, String> {
+    let mut tokens = line.split_whitespace();
+    let mut next = || tokens.next().ok_or("Truncated line");
+    let cycle = next()?.parse().map_err(|_| "Invalid cycle")?;
+    let pc = hex(next()?)?;
+    let opcode = hex(next()?)?;
+    let mut entry = Entry {
+        cycle,
+        pc,
+        opcode: Some(opcode),
+        writes: Some(Vec::new()),
+        ..Default::default()
+    };
+
+    for token in line.split_whitespace().skip(3) {
+        if let Some(i) = token.strip_prefix("I=") {
+            entry.i = Some(hex(i)?);
+        } else if let (Some(write), true) = (token.strip_prefix('['), token.contains("]=")) {
+            let (addr, value) = write.split_once("]=").ok_or("Invalid memory write")?;
+            let write = (hex(addr)?, hex(value)? as u8);
+            entry.writes.get_or_insert_with(Vec::new).push(write);
+        } else if let (Some(change), true) = (token.strip_prefix('V'), token.contains('>')) {
+            let (x, values) = change.split_once('=').ok_or("Invalid register change")?;
+            let (_, new) = values.split_once('>').ok_or("Invalid register change")?;
+            v[hex(x)? as usize & 0xF] = hex(new)? as u8;
+        }
+    }
+    entry.v = Some(*v);
+    Ok(entry)
+}
+
+fn parse_simple(line: &str, default_cycle: u64) -> std::result::Result<Entry, String> {
+    let mut entry = Entry {
+        cycle: default_cycle,
+        ..Default::default()
+    };
+    let mut pc = None;
+    let mut v = [None; 16];
+
+    for token in line.split_whitespace() {
+        let (key, value) = token
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value, found {}", token))?;
+        match key.to_ascii_lowercase().as_str() {
+            "cycle" => entry.cycle = value.parse().map_err(|_| "Invalid cycle")?,
+            "pc" => pc = Some(hex(value)?),
+            "op" => entry.opcode = Some(hex(value)?),
+            "i" => entry.i = Some(hex(value)?),
+            "w" => {
+                let mut writes = Vec::new();
+                for write in value.split(',').filter(|w| !w.is_empty()) {
+                    let (addr, value) = write.split_once(':').ok_or("Invalid memory write")?;
+                    writes.push((hex(addr)?, hex(value)? as u8));
+                }
+                entry.writes = Some(writes);
+            }
+            key if key.len() == 2 && key.starts_with('v') => {
+                let x = hex(&key[1..])? as usize;
+                v[x] = Some(hex(value)? as u8);
+            }
+            // Unknown fields are allowed, e.g. timers
+            _ => (),
+        }
+    }
+    entry.pc = pc.ok_or("Missing pc")?;
+    // Registers are only compared when all of them are present
+    if v.iter().all(Option::is_some) {
+        let mut registers = [0; 16];
+        for (r, value) in registers.iter_mut().zip(v.iter()) {
+            *r = value.unwrap();
+        }
+        entry.v = Some(registers);
+    }
+    Ok(entry)
+}
+
+pub struct Divergence {
+    // Index of the first divergent entry in each trace
+    pub a: usize,
+    pub b: usize,
+    pub differences: Vec<String>,
+}
+
+/// Differences between two entries on the fields both of them carry
+pub fn compare(a: &Entry, b: &Entry) -> Vec<String> {
+    let mut differences = Vec::new();
+    if a.pc != b.pc {
+        differences.push(format!("PC: {:#06X} != {:#06X}", a.pc, b.pc));
+    }
+    if let (Some(x), Some(y)) = (a.opcode, b.opcode) {
+        if x != y {
+            differences.push(format!("opcode: {:#06X} != {:#06X}", x, y));
+        }
+    }
+    if let (Some(x), Some(y)) = (a.v, b.v) {
+        for r in 0..16 {
+            if x[r] != y[r] {
+                differences.push(format!("V{:X}: {:#04X} != {:#04X}", r, x[r], y[r]));
+            }
+        }
+    }
+    if let (Some(x), Some(y)) = (a.i, b.i) {
+        if x != y {
+            differences.push(format!("I: {:#06X} != {:#06X}", x, y));
+        }
+    }
+    if let (Some(x), Some(y)) = (&a.writes, &b.writes) {
+        if x != y {
+            let show = |writes: &[(u16, u8)]| {
+                writes
+                    .iter()
+                    .map(|(addr, value)| format!("[{:04X}]={:02X}", addr, value))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            differences.push(format!("writes: {} != {}", show(x), show(y)));
+        }
+    }
+    differences
+}
+
+/// Align both traces by cycle and find the first entry that differs,
+/// cycles that only show up in one of them (e.g. filtered) are skipped
+pub fn first_divergence(a: &[Entry], b: &[Entry]) -> Option<Divergence> {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cycle.cmp(&b[j].cycle) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                let differences = compare(&a[i], &b[j]);
+                if !differences.is_empty() {
+                    return Some(Divergence {
+                        a: i,
+                        b: j,
+                        differences,
+                    });
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    None
+}
+
+fn print_context(name: &str, entries: &[Entry], at: usize, context: usize) {
+    println!("{}", name);
+    let start = at.saturating_sub(context);
+    let end = (at + context + 1).min(entries.len());
+    for (k, entry) in entries.iter().enumerate().take(end).skip(start) {
+        let marker = if k == at { '>' } else { ' ' };
+        println!("{} {:>6}: {}", marker, entry.line_no, entry.line);
+    }
+}
+
+/// Print the first divergence between the traces with `context` lines around it,
+/// returns whether they diverge
+pub fn run<P: AsRef<Path>>(a: P, b: P, context: usize) -> Result<bool> {
+    let trace_a = read_trace(&a)?;
+    let trace_b = read_trace(&b)?;
+
+    match first_divergence(&trace_a, &trace_b) {
+        Some(divergence) => {
+            println!("First divergence at cycle {}:", trace_a[divergence.a].cycle);
+            for difference in &divergence.differences {
+                println!("  {}", difference);
+            }
+            println!();
+            print_context(
+                &a.as_ref().display().to_string(),
+                &trace_a,
+                divergence.a,
+                context,
+            );
+            println!();
+            print_context(
+                &b.as_ref().display().to_string(),
+                &trace_b,
+                divergence.b,
+                context,
+            );
+            Ok(true)
+        }
+        None => {
+            let last = |t: &[Entry]| t.last().map_or(0, |e| e.cycle);
+            println!(
+                "No divergence, traces end at cycle {} and {}",
+                last(&trace_a),
+                last(&trace_b)
+            );
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_all_formats_alike() {
+        let mut v = [0; 16];
+        let text = parse_text(
+            "       3  0204  600C  LD V0 0x0C         I=022A DT=00 ST=00  V0=00>0C",
+            &mut v,
+        )
+        .unwrap();
+        let json = parse_json(r#"{"cycle":3,"pc":516,"opcode":24588,"mnemonic":"LD V0 0x0C","v":[12,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"changed":[[0,0,12]],"i":554,"dt":0,"st":0,"writes":[]}"#).unwrap();
+        let simple = parse_simple(
+            "pc=0204 op=600C i=022A v0=0C v1=0 v2=0 v3=0 v4=0 v5=0 v6=0 v7=0 \
+             v8=0 v9=0 va=0 vb=0 vc=0 vd=0 ve=0 vf=0 w=",
+            3,
+        )
+        .unwrap();
+        assert_eq!(text, json);
+        assert_eq!(json, simple);
+    }
+
+    #[test]
+    fn it_finds_first_divergence() {
+        let entry = |cycle, pc, i| Entry {
+            cycle,
+            pc,
+            i: Some(i),
+            ..Default::default()
+        };
+        let a = vec![entry(1, 0x200, 0), entry(2, 0x202, 0), entry(3, 0x204, 1)];
+        // Filtered out the first cycle
+        let b = vec![entry(2, 0x202, 0), entry(3, 0x204, 2)];
+        let divergence = first_divergence(&a, &b).unwrap();
+        assert_eq!((divergence.a, divergence.b), (2, 1));
+        assert_eq!(divergence.differences, vec!["I: 0x0001 != 0x0002"]);
+    }
+
+    #[test]
+    fn it_leaves_registers_of_filtered_text_traces_out() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let trace = |name: &str, lines: &[&str]| {
+            let path = dir.path().join(name);
+            fs::write(&path, lines.join("\n")).map(|_| path)
+        };
+        let first = "       1  0200  600C  LD V0 0x0C         I=0000 DT=00 ST=00  V0=00>0C";
+        let second = "       2  0202  6103  LD V1 0x03         I=0000 DT=00 ST=00  V1=00>03";
+        let full = read_trace(trace("full.txt", &[first, second])?)?;
+        assert_eq!(full[1].v.map(|v| (v[0], v[1])), Some((0x0C, 0x03)));
+
+        // V0 was set before the range
+        let filtered = read_trace(trace("filtered.txt", &[second])?)?;
+        assert_eq!(filtered[0].v, None);
+        let third = "       4  0206  7101  ADD V1 0x01        I=0000 DT=00 ST=00  V1=03>04";
+        let gap = read_trace(trace("gap.txt", &[first, second, third])?)?;
+        assert!(gap[1].v.is_some() && gap[2].v.is_none());
+        Ok(())
+    }
+}